@@ -0,0 +1,18 @@
+// Prints assets/database.txt lines for every game in the NES 2.0 XML database, meant to
+// replace the generated part at the end of that file
+use nemulator::rom_reader::database;
+
+fn main() {
+    let Some(path) = std::env::args().nth(1) else {
+        eprintln!("Usage: import_nes20db <nes20db.xml>");
+        std::process::exit(1);
+    };
+    let xml = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+        eprintln!("Failed to read {path}: {e}");
+        std::process::exit(1);
+    });
+
+    for line in database::from_nes20db(&xml) {
+        println!("{line}");
+    }
+}
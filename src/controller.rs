@@ -0,0 +1,49 @@
+#[cfg(test)]
+mod tests;
+
+// Button bits for set_buttons, in the order the shift register reports them
+pub const BUTTON_A: u8 = 0b0000_0001;
+pub const BUTTON_B: u8 = 0b0000_0010;
+pub const BUTTON_SELECT: u8 = 0b0000_0100;
+pub const BUTTON_START: u8 = 0b0000_1000;
+pub const BUTTON_UP: u8 = 0b0001_0000;
+pub const BUTTON_DOWN: u8 = 0b0010_0000;
+pub const BUTTON_LEFT: u8 = 0b0100_0000;
+pub const BUTTON_RIGHT: u8 = 0b1000_0000;
+
+// Standard controller, a shift register latching the buttons,
+// see https://www.nesdev.org/wiki/Standard_controller
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Controller {
+    buttons: u8,
+    shift: u8,
+    // While $4016 bit 0 is high the register keeps reloading and reads always report A
+    strobe: bool,
+}
+
+impl Controller {
+    pub fn set_buttons(&mut self, buttons: u8) {
+        self.buttons = buttons;
+        if self.strobe {
+            self.shift = buttons;
+        }
+    }
+
+    pub fn set_strobe(&mut self, strobe: bool) {
+        self.strobe = strobe;
+        if strobe {
+            self.shift = self.buttons;
+        }
+    }
+
+    // Bit 0 of a $4016/$4017 read, official controllers return 1 once all 8 buttons are out
+    pub fn read(&mut self) -> u8 {
+        if self.strobe {
+            return self.buttons & 1;
+        }
+        let bit = self.shift & 1;
+        self.shift = (self.shift >> 1) | 0b1000_0000;
+
+        bit
+    }
+}
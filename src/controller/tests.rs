@@ -0,0 +1,21 @@
+use crate::controller::{BUTTON_A, BUTTON_START, Controller};
+
+#[test]
+fn shift_register() {
+    let mut controller = Controller::default();
+    controller.set_buttons(BUTTON_A | BUTTON_START);
+
+    // Strobe high keeps reporting A
+    controller.set_strobe(true);
+    assert_eq!((controller.read(), controller.read()), (1, 1));
+
+    controller.set_strobe(false);
+    let bits: Vec<u8> = (0..10).map(|_| controller.read()).collect();
+    assert_eq!(bits, [1, 0, 0, 1, 0, 0, 0, 0, 1, 1]);
+
+    // Buttons changing after the latch don't show up until the next strobe
+    controller.set_strobe(true);
+    controller.set_strobe(false);
+    controller.set_buttons(0);
+    assert_eq!(controller.read(), 1);
+}
@@ -38,6 +38,11 @@ pub struct CPU {
     log_file: Option<File>,
     irq: bool,
     nmi: bool,
+    // NMI is edge triggered, remember the line level to spot the rising edge
+    nmi_line: bool,
+    // Set when DMA, an interrupt or a reset added cycles after an instruction, the next
+    // instruction has to wait for them and then fetch as usual
+    stalled: bool,
 }
 
 impl CPU {
@@ -66,9 +71,61 @@ impl CPU {
             log_file,
             irq: false,
             nmi: false,
+            nmi_line: false,
+            stalled: false,
         }
     }
 
+    // The next cycle() starts a new instruction, registers can be touched safely
+    pub fn at_instruction_boundary(&self, emulator_cycle: u64) -> bool {
+        self.cycle == emulator_cycle
+    }
+
+    pub fn program_counter(&self) -> u16 {
+        self.program_counter
+    }
+
+    // The reset button runs the interrupt sequence with writes suppressed, so the stack
+    // pointer moves down by 3 without touching memory
+    pub fn reset(&mut self, memory: &mut Memory, emulator_cycle: u64) {
+        self.program_counter = u16::from_le_bytes([memory.get(0xFFFC), memory.get(0xFFFD)]);
+        self.stack_pointer = self.stack_pointer.wrapping_sub(3);
+        self.set_flag_interrupt_disable(true);
+        self.cycle = emulator_cycle + 7;
+        self.stalled = true;
+        self.nmi = false;
+    }
+
+    pub fn reset_stack(&mut self) {
+        self.stack_pointer = 0xFD;
+    }
+
+    // Calls a subroutine like JSR would, RTS lands on return_address
+    pub fn call(&mut self, memory: &mut Memory, address: u16, return_address: u16, a: u8, x: u8) {
+        let pushed = return_address.wrapping_sub(1);
+        memory.set(0x100 + self.stack_pointer as u16, (pushed >> 8) as u8);
+        self.stack_pointer = self.stack_pointer.wrapping_sub(1);
+        memory.set(0x100 + self.stack_pointer as u16, pushed as u8);
+        self.stack_pointer = self.stack_pointer.wrapping_sub(1);
+
+        self.program_counter = address;
+        self.accumulator = a;
+        self.index_x = x;
+        self.index_y = 0;
+    }
+
+    // IRQ is level triggered, the line stays asserted until the source is acknowledged
+    pub fn set_irq(&mut self, value: bool) {
+        self.irq = value;
+    }
+
+    pub fn set_nmi(&mut self, line: bool) {
+        if line && !self.nmi_line {
+            self.nmi = true;
+        }
+        self.nmi_line = line;
+    }
+
     fn get_flag_carry(&self) -> bool {
         self.status_register & 0b0000_0001 != 0
     }
@@ -509,7 +566,7 @@ impl CPU {
         let address = u16::from_le_bytes([address_lb, address_hb]);
 
         let value = memory.get(address);
-        let result = callback(register, memory.get(address));
+        let result = callback(register, value);
 
         Some((value, result))
     }
@@ -549,7 +606,7 @@ impl CPU {
         address += self.index_x as u16;
 
         let value = memory.get(address);
-        let result = callback(register, memory.get(address));
+        let result = callback(register, value);
 
         Some((value, result))
     }
@@ -589,7 +646,7 @@ impl CPU {
         address = address.wrapping_add(self.index_y as u16);
 
         let value = memory.get(address);
-        let result = callback(register, memory.get(address));
+        let result = callback(register, value);
 
         Some((value, result))
     }
@@ -622,7 +679,7 @@ impl CPU {
         ]);
 
         let value = memory.get(address);
-        let result = callback(register, memory.get(address));
+        let result = callback(register, value);
 
         Some((value, result))
     }
@@ -688,7 +745,7 @@ impl CPU {
         let address = u16::from_le_bytes([lo, hi]);
 
         let value = memory.get(address);
-        let result = callback(register, memory.get(address));
+        let result = callback(register, value);
 
         Some((value, result))
     }
@@ -716,7 +773,7 @@ impl CPU {
         self.program_counter = self.program_counter.wrapping_add(1);
 
         let value = memory.get(address);
-        let result = callback(register, memory.get(address));
+        let result = callback(register, value);
 
         Some((value, result))
     }
@@ -744,7 +801,7 @@ impl CPU {
         let address = lookup as u16;
 
         let value = memory.get(address);
-        let result = callback(register, memory.get(address));
+        let result = callback(register, value);
 
         Some((value, result))
     }
@@ -772,7 +829,7 @@ impl CPU {
         let address = lookup as u16;
 
         let value = memory.get(address);
-        let result = callback(register, memory.get(address));
+        let result = callback(register, value);
 
         Some((value, result))
     }
@@ -942,9 +999,10 @@ impl CPU {
     }
 
     pub fn cycle(&mut self, memory: &mut Memory, emulator_cycle: u64) -> Result<(), String> {
-        if self.cycle - 1 > emulator_cycle {
+        if self.cycle - 1 > emulator_cycle || (self.stalled && self.cycle > emulator_cycle) {
             return Ok(());
         }
+        self.stalled = false;
 
         let mut set_interrupt = false;
         let mut interrupt_value = false;
@@ -2057,7 +2115,14 @@ impl CPU {
             }
         }
 
-        if self.nmi | self.irq {
+        // OAM DMA halts the CPU after the $4014 write: one halt cycle, one more to line up
+        // with a read cycle when the write landed on an odd cycle, then 256 read/write pairs
+        if memory.take_oam_dma() {
+            self.cycle += if emulator_cycle % 2 == 1 { 514 } else { 513 };
+            self.stalled = true;
+        }
+
+        if self.nmi | (self.irq && !self.get_flag_interrupt_disable()) {
             memory.set(
                 0x100 + self.stack_pointer as u16,
                 (self.program_counter >> 8) as u8,
@@ -2076,6 +2141,7 @@ impl CPU {
             self.set_flag_interrupt_disable(true);
 
             if self.nmi {
+                self.nmi = false;
                 self.program_counter = u16::from_le_bytes([memory.get(0xFFFA), memory.get(0xFFFB)]);
             } else {
                 self.program_counter = u16::from_le_bytes([memory.get(0xFFFE), memory.get(0xFFFF)]);
@@ -2083,6 +2149,7 @@ impl CPU {
 
             // TODO: It has to skip cycles before jumping to the interrupt subroutine. Now it jumps, then skips cycles
             self.cycle += 7;
+            self.stalled = true;
         }
 
         if set_interrupt {
@@ -59,13 +59,15 @@ use crate::{Memory, cpu::CPU, rom_reader};
 
 #[test]
 fn cpu_full() {
-    let file = rom_reader::read_file("./assets/tests/nestest_old.nes");
+    let file = rom_reader::read_file("./assets/tests/nestest_old.nes").unwrap();
     let mut memory = Memory::new(
         vec![0; 0x800],
         PPURegisters::new(),
         [0; 32],
         file.prg_rom,
         file.chr_rom,
+        vec![0; file.header.chr_ram_size],
+        vec![0; file.header.prg_ram_size + file.header.prg_nvram_size],
     );
 
     let mut cpu = CPU::new(&mut memory, Some("test-cpu_full.log"));
@@ -266,3 +268,59 @@ fn cpu_full() {
         line_number += 1;
     }
 }
+
+#[test]
+fn nmi_edge() {
+    // NOPs everywhere, NMI vector $9000
+    let mut prg_rom = vec![0xEA; 0x8000];
+    prg_rom[0x7FFA..0x7FFE].copy_from_slice(&[0x00, 0x90, 0x00, 0x80]);
+    let mut memory = Memory::with_prg(prg_rom).with_chr_rom(vec![0; 0x2000]);
+    let mut cpu = CPU::new(&mut memory, None);
+
+    // Holding the line high only fires once
+    cpu.set_nmi(true);
+    cpu.cycle(&mut memory, 7).unwrap();
+    cpu.cycle(&mut memory, 8).unwrap();
+    assert_eq!(cpu.program_counter(), 0x9000);
+    cpu.set_nmi(true);
+    for emulator_cycle in 9..40 {
+        cpu.cycle(&mut memory, emulator_cycle).unwrap();
+    }
+    assert!(cpu.program_counter() < 0x9020);
+
+    cpu.set_nmi(false);
+    cpu.set_nmi(true);
+    for emulator_cycle in 40..50 {
+        cpu.cycle(&mut memory, emulator_cycle).unwrap();
+    }
+    assert_eq!(cpu.program_counter() & 0xFFF0, 0x9000);
+}
+
+#[test]
+fn irq_mask() {
+    // NOP, CLI, then NOPs, IRQ vector $9000
+    let mut prg_rom = vec![0xEA; 0x8000];
+    prg_rom[1] = 0x58;
+    prg_rom[0x7FFC..0x8000].copy_from_slice(&[0x00, 0x80, 0x00, 0x90]);
+    let mut memory = Memory::with_prg(prg_rom).with_chr_rom(vec![0; 0x2000]);
+    let mut cpu = CPU::new(&mut memory, None);
+
+    // I is set at power on, the NOP and the CLI run with the line held low
+    cpu.set_irq(true);
+    for emulator_cycle in 7..11 {
+        cpu.cycle(&mut memory, emulator_cycle).unwrap();
+    }
+    assert_eq!(cpu.program_counter(), 0x8002);
+
+    // CLI takes effect after the next instruction
+    cpu.cycle(&mut memory, 11).unwrap();
+    cpu.cycle(&mut memory, 12).unwrap();
+    assert_eq!(cpu.program_counter(), 0x9000);
+    assert_eq!(memory.get(0x01FB) & 0b0000_0100, 0);
+
+    // The handler runs with I set, so the line still being low doesn't re-enter it
+    for emulator_cycle in 13..40 {
+        cpu.cycle(&mut memory, emulator_cycle).unwrap();
+    }
+    assert!(cpu.program_counter() > 0x9002);
+}
@@ -0,0 +1,172 @@
+use crate::memory::Memory;
+
+#[cfg(test)]
+mod tests;
+
+// The four logical nametables in a 2x2 grid
+pub const NAMETABLES_WIDTH: usize = 512;
+pub const NAMETABLES_HEIGHT: usize = 480;
+
+// Both pattern tables side by side, 16x16 tiles each
+pub const PATTERN_TABLES_WIDTH: usize = 256;
+pub const PATTERN_TABLES_HEIGHT: usize = 128;
+
+// One OAM entry
+pub struct Sprite {
+    pub y: u8,
+    pub tile: u8,
+    pub attributes: u8,
+    pub x: u8,
+}
+
+impl Sprite {
+    // Sprites use palettes 4-7
+    pub fn palette(&self) -> u8 {
+        4 + (self.attributes & 0b11)
+    }
+
+    pub fn behind_background(&self) -> bool {
+        self.attributes & 0b0010_0000 != 0
+    }
+
+    pub fn flip_horizontal(&self) -> bool {
+        self.attributes & 0b0100_0000 != 0
+    }
+
+    pub fn flip_vertical(&self) -> bool {
+        self.attributes & 0b1000_0000 != 0
+    }
+}
+
+pub struct NametableTile {
+    pub address: u16,
+    pub tile: u8,
+    pub attribute_address: u16,
+    pub attribute: u8,
+    pub palette: u8,
+}
+
+// The tile under pixel x, y of the nametables image
+pub fn nametable_tile(memory: &Memory, x: usize, y: usize) -> NametableTile {
+    let base = 0x2000 + ((y / 240) * 2 + x / 256) as u16 * 0x400;
+    let (column, row) = ((x % 256 / 8) as u16, (y % 240 / 8) as u16);
+    let address = base + row * 32 + column;
+    let attribute_address = base + 0x3C0 + (row / 4) * 8 + column / 4;
+    let attribute = memory.ppu_get(attribute_address);
+    // Each attribute byte covers 4x4 tiles, 2 bits per 2x2 quadrant
+    let shift = ((row & 0b10) << 1) | (column & 0b10);
+
+    NametableTile {
+        address,
+        tile: memory.ppu_get(address),
+        attribute_address,
+        attribute,
+        palette: (attribute >> shift) & 0b11,
+    }
+}
+
+// Color of pixel 0-3 in one of the 8 palettes, pixel 0 is always the backdrop
+pub fn palette_color(memory: &Memory, palette: u8, pixel: u8) -> u16 {
+    let address = if pixel == 0 {
+        0x3F00
+    } else {
+        0x3F00 + palette as u16 * 4 + pixel as u16
+    };
+    (memory.ppu_get(address) & 0x3F) as u16
+}
+
+// Row of 8 pixels, 0-3, of a tile in CHR
+fn tile_row(memory: &Memory, address: u16) -> [u8; 8] {
+    let pattern_lo = memory.ppu_get(address);
+    let pattern_hi = memory.ppu_get(address + 8);
+    std::array::from_fn(|x| ((pattern_lo >> (7 - x)) & 1) | (((pattern_hi >> (7 - x)) & 1) << 1))
+}
+
+// All four nametables through the current mirroring, drawn with the background pattern table
+// and their attribute palettes
+pub fn nametables(memory: &Memory) -> Vec<u16> {
+    let pattern_table = memory.ppu_registers.get_ppuctrl_background_table();
+    let mut pixels = vec![0; NAMETABLES_WIDTH * NAMETABLES_HEIGHT];
+    for tile_y in (0..NAMETABLES_HEIGHT).step_by(8) {
+        for tile_x in (0..NAMETABLES_WIDTH).step_by(8) {
+            let tile = nametable_tile(memory, tile_x, tile_y);
+            for y in 0..8 {
+                let row = tile_row(memory, pattern_table + tile.tile as u16 * 16 + y as u16);
+                for (x, &pixel) in row.iter().enumerate() {
+                    pixels[(tile_y + y) * NAMETABLES_WIDTH + tile_x + x] =
+                        palette_color(memory, tile.palette, pixel);
+                }
+            }
+        }
+    }
+
+    pixels
+}
+
+// Both pattern tables colored with one of the 8 palettes, read through ppu_get so CHR-RAM
+// writes and bank switches show up on the next call
+pub fn pattern_tables(memory: &Memory, palette: u8) -> Vec<u16> {
+    let mut pixels = vec![0; PATTERN_TABLES_WIDTH * PATTERN_TABLES_HEIGHT];
+    for tile in 0..512 {
+        let tile_x = (tile / 256) * 128 + (tile % 16) * 8;
+        let tile_y = (tile % 256 / 16) * 8;
+        for y in 0..8 {
+            let row = tile_row(memory, (tile * 16 + y) as u16);
+            for (x, &pixel) in row.iter().enumerate() {
+                pixels[(tile_y + y) * PATTERN_TABLES_WIDTH + tile_x + x] =
+                    palette_color(memory, palette, pixel);
+            }
+        }
+    }
+
+    pixels
+}
+
+pub fn sprites(memory: &Memory) -> Vec<Sprite> {
+    memory
+        .ppu_registers
+        .oam()
+        .chunks_exact(4)
+        .map(|entry| Sprite {
+            y: entry[0],
+            tile: entry[1],
+            attributes: entry[2],
+            x: entry[3],
+        })
+        .collect()
+}
+
+// Pixels 0-3 of a sprite as it appears on screen, 8 wide and 8 or 16 tall, flips applied
+pub fn sprite_pixels(memory: &Memory, sprite: &Sprite) -> Vec<u8> {
+    let height = memory.ppu_registers.get_ppuctrl_sprite_height();
+    (0..height)
+        .flat_map(|y| {
+            let row = if sprite.flip_vertical() {
+                height - 1 - y
+            } else {
+                y
+            };
+            let mut pixels = tile_row(
+                memory,
+                memory.ppu_registers.sprite_row_address(sprite.tile, row),
+            );
+            if sprite.flip_horizontal() {
+                pixels.reverse();
+            }
+            pixels
+        })
+        .collect()
+}
+
+// OAM indices of the sprites covering a screen line, in the order evaluation finds them.
+// Everything past the eighth is dropped by the PPU
+pub fn sprites_on_line(memory: &Memory, line: u16) -> Vec<usize> {
+    let height = memory.ppu_registers.get_ppuctrl_sprite_height();
+    // Y is one less than the first line a sprite shows up on
+    sprites(memory)
+        .iter()
+        .enumerate()
+        .filter(|(_, sprite)| line > sprite.y as u16 && line <= sprite.y as u16 + height)
+        .map(|(index, _)| index)
+        .collect()
+}
@@ -0,0 +1,78 @@
+use crate::debug::{
+    NAMETABLES_WIDTH, PATTERN_TABLES_WIDTH, nametable_tile, nametables, pattern_tables,
+    sprite_pixels, sprites, sprites_on_line,
+};
+use crate::memory::Memory;
+use crate::rom_reader::Mirroring;
+
+#[test]
+fn nametable_view() {
+    // Tile 1 is solid color 3
+    let mut chr_rom = vec![0; 0x2000];
+    chr_rom[16..32].fill(0xFF);
+    let mut memory = Memory::with_prg(vec![0; 0x4000]).with_chr_rom(chr_rom);
+    memory.set_mirroring(Mirroring::Vertical);
+    // Row 2, column 3 of the top right nametable, bottom right quadrant of its attribute byte
+    memory.ppu_set(0x2400 + 2 * 32 + 3, 0x01);
+    memory.ppu_set(0x27C0, 0b1000_0000);
+    memory.ppu_set(0x3F00, 0x0F);
+    memory.ppu_set(0x3F0B, 0x16);
+
+    let tile = nametable_tile(&memory, 256 + 3 * 8, 2 * 8 + 5);
+    assert_eq!((tile.address, tile.tile), (0x2443, 0x01));
+    assert_eq!((tile.attribute_address, tile.palette), (0x27C0, 2));
+    // Vertical mirroring repeats it in the bottom right nametable
+    assert_eq!(nametable_tile(&memory, 256 + 3 * 8, 240 + 2 * 8).tile, 0x01);
+
+    let pixels = nametables(&memory);
+    assert_eq!(pixels[16 * NAMETABLES_WIDTH + 256 + 24], 0x16);
+    assert_eq!(pixels[(240 + 16) * NAMETABLES_WIDTH + 256 + 31], 0x16);
+    assert_eq!(pixels[16 * NAMETABLES_WIDTH + 256 + 32], 0x0F);
+}
+
+#[test]
+fn pattern_table_view() {
+    let mut memory = Memory::with_prg(vec![0; 0x4000]);
+    // Tile $12 of the $1000 table, top row in color 2
+    memory.ppu_set(0x1000 + 0x12 * 16 + 8, 0xFF);
+    memory.ppu_set(0x3F00, 0x0F);
+    memory.ppu_set(0x3F16, 0x2A);
+
+    let pixels = pattern_tables(&memory, 5);
+    let (x, y) = (128 + 2 * 8, 8);
+    assert_eq!(pixels[y * PATTERN_TABLES_WIDTH + x..][..8], [0x2A; 8]);
+    assert_eq!(pixels[(y + 1) * PATTERN_TABLES_WIDTH + x], 0x0F);
+    // The same tile of the $0000 table is empty
+    assert_eq!(pixels[y * PATTERN_TABLES_WIDTH + x - 128], 0x0F);
+}
+
+#[test]
+fn sprite_view() {
+    let mut memory = Memory::with_prg(vec![0; 0x4000]);
+    memory.ppu_registers.set(0x2003, 0);
+    for _ in 0..256 {
+        memory.ppu_registers.set(0x2004, 0xFF);
+    }
+    // Ten sprites at Y=49, tile 3 flipped both ways on the first one
+    memory.ppu_registers.set(0x2003, 0);
+    for sprite in 0..10 {
+        let attributes = if sprite == 0 { 0b1100_0001 } else { 0 };
+        for value in [49, 3, attributes, sprite * 8] {
+            memory.ppu_registers.set(0x2004, value);
+        }
+    }
+    // Top left pixel of tile 3 in color 1
+    memory.ppu_set(3 * 16, 0b1000_0000);
+
+    let sprites = sprites(&memory);
+    assert_eq!(sprites[0].palette(), 5);
+    let pixels = sprite_pixels(&memory, &sprites[0]);
+    assert_eq!(pixels.len(), 64);
+    assert_eq!(pixels[7 * 8 + 7], 1);
+    assert_eq!(pixels.iter().filter(|&&pixel| pixel != 0).count(), 1);
+
+    assert_eq!(sprites_on_line(&memory, 49), []);
+    assert_eq!(sprites_on_line(&memory, 50), (0..10).collect::<Vec<_>>());
+    assert_eq!(sprites_on_line(&memory, 57).len(), 10);
+    assert_eq!(sprites_on_line(&memory, 58), []);
+}
@@ -0,0 +1,224 @@
+use std::path::{Path, PathBuf};
+
+use crate::cpu::CPU;
+use crate::fds::Fds;
+use crate::memory::Memory;
+use crate::nsf::NsfPlayer;
+use crate::ppu::{PPURegisters, ppu_cycle};
+use crate::region::Region;
+use crate::rom_reader::{HeaderFormat, RomError, fds, iNES};
+
+#[cfg(test)]
+mod tests;
+
+// Battery-backed PRG-RAM is written back about once per second of emulation
+const SAVE_FLUSH_INTERVAL: u64 = 60;
+
+pub struct Emulator {
+    cpu: CPU,
+    memory: Memory,
+    region: Region,
+    // Every other clock is derived from the master clock by its region's divider
+    master_clock: u64,
+    next_cpu_clock: u64,
+    next_ppu_clock: u64,
+    cpu_cycle: u64,
+    frame: u64,
+    battery: bool,
+    save_path: Option<PathBuf>,
+    disk_save_path: Option<PathBuf>,
+    nsf_player: Option<NsfPlayer>,
+    // There is no APU yet, so this stays empty and NSFs play silently
+    audio_samples: Vec<f32>,
+}
+
+impl Emulator {
+    // Powers on a console with the cartridge, disk or NSF from rom_reader inserted.
+    // Disk images need fds::load_bios called on them first
+    pub fn load(rom: iNES) -> Result<Emulator, RomError> {
+        if rom.header.format == HeaderFormat::Fds {
+            match rom.prg_rom.len() {
+                0 => return Err(RomError::MissingBios),
+                fds::BIOS_SIZE => {}
+                size => return Err(RomError::BadBios(size)),
+            }
+        }
+
+        let mut memory = Memory::new(
+            vec![0; 0x800],
+            PPURegisters::new(),
+            [0; 32],
+            rom.prg_rom,
+            rom.chr_rom,
+            vec![0; rom.header.chr_ram_size + rom.header.chr_nvram_size],
+            vec![0; rom.header.prg_ram_size + rom.header.prg_nvram_size],
+        );
+        memory.set_mirroring(rom.header.mirroring);
+        if rom.header.trainer {
+            memory.load_trainer(&rom.trainer);
+        }
+        if rom.header.format == HeaderFormat::Fds {
+            memory.attach_fds(Fds::new(rom.disk_sides));
+        }
+        let region = Region::from_timing(rom.header.timing);
+        memory.ppu_registers.set_region(region);
+
+        Ok(Emulator {
+            cpu: CPU::new(&mut memory, None),
+            memory,
+            region,
+            master_clock: 0,
+            next_cpu_clock: 0,
+            next_ppu_clock: 0,
+            cpu_cycle: 7,
+            frame: 0,
+            battery: rom.header.battery,
+            save_path: None,
+            disk_save_path: None,
+            nsf_player: rom.nsf.map(NsfPlayer::new),
+            audio_samples: Vec::new(),
+        })
+    }
+
+    // Keeps battery saves in rom_path.sav and disk writes in rom_path.fdsdiff, loading
+    // whatever is already there. Without this nothing is written to disk
+    pub fn load_save_files(&mut self, rom_path: &Path) {
+        if self.battery {
+            let save_path = rom_path.with_extension("sav");
+            if let Err(e) = self.memory.load_prg_ram(&save_path) {
+                eprintln!("Failed to read {}: {e}", save_path.display());
+            }
+            self.save_path = Some(save_path);
+        }
+        if let Some(fds) = self.memory.fds_mut() {
+            let disk_save_path = rom_path.with_extension("fdsdiff");
+            if let Err(e) = fds.load_diff(&disk_save_path) {
+                eprintln!("Failed to read {}: {e}", disk_save_path.display());
+            }
+            self.disk_save_path = Some(disk_save_path);
+        }
+    }
+
+    // Overrides the region from the header, meant to be called right after load
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.memory.ppu_registers.set_region(region);
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    // Moves the master clock to the next CPU cycle or PPU dot, running both when they line up.
+    // The CPU goes first on a tie so the PPU sees its register writes on the same dot
+    fn step(&mut self) -> Result<(), String> {
+        self.master_clock = self.next_cpu_clock.min(self.next_ppu_clock);
+        if self.next_cpu_clock == self.master_clock {
+            self.cpu_step()?;
+            self.next_cpu_clock += self.region.cpu_divider();
+        }
+        if self.next_ppu_clock == self.master_clock {
+            ppu_cycle(&mut self.memory);
+            self.next_ppu_clock += self.region.ppu_divider();
+        }
+        Ok(())
+    }
+
+    // Cartridge hardware runs off M2 like the CPU, the APU will too once there is one
+    fn cpu_step(&mut self) -> Result<(), String> {
+        self.memory.tick();
+        if let Some(nsf_player) = &mut self.nsf_player {
+            nsf_player.cycle(&mut self.cpu, &mut self.memory, self.cpu_cycle);
+        }
+        self.cpu.set_irq(self.memory.irq());
+        self.cpu.cycle(&mut self.memory, self.cpu_cycle)?;
+        // Sampled after the CPU cycle so a PPUSTATUS read can still pull the line down
+        self.cpu.set_nmi(self.memory.nmi());
+        self.cpu_cycle += 1;
+        Ok(())
+    }
+
+    // Runs until the PPU signals the end of the pre-render line, the error names an opcode the
+    // CPU can't run
+    pub fn run_frame(&mut self) -> Result<(), String> {
+        self.audio_samples.clear();
+        // A frame that ended during step_instruction doesn't count
+        self.memory.ppu_registers.take_frame_complete();
+        while !self.memory.ppu_registers.take_frame_complete() {
+            self.step()?;
+        }
+
+        self.frame += 1;
+        if self.frame.is_multiple_of(SAVE_FLUSH_INTERVAL) {
+            self.flush_save();
+        }
+        Ok(())
+    }
+
+    // Finishes the current instruction and stops right before the next one
+    pub fn step_instruction(&mut self) -> Result<(), String> {
+        let start = self.cpu_cycle;
+        loop {
+            self.step()?;
+            if self.cpu_cycle > start && self.cpu.at_instruction_boundary(self.cpu_cycle) {
+                return Ok(());
+            }
+        }
+    }
+
+    // The reset button, RAM and the cartridge keep their contents
+    pub fn reset(&mut self) {
+        self.memory.ppu_registers.reset();
+        self.cpu.reset(&mut self.memory, self.cpu_cycle);
+        if let Some(nsf_player) = &mut self.nsf_player {
+            nsf_player.select_song(nsf_player.song());
+        }
+    }
+
+    // Palette indices, emphasis << 6 | color, SCREEN_WIDTH by SCREEN_HEIGHT
+    pub fn framebuffer(&self) -> &[u16] {
+        self.memory.ppu_registers.framebuffer()
+    }
+
+    // Samples produced by the last run_frame
+    pub fn audio_samples(&self) -> &[f32] {
+        &self.audio_samples
+    }
+
+    // Pressed buttons of the controller in port 0 or 1, see the controller::BUTTON_* bits
+    pub fn set_input(&mut self, port: usize, buttons: u8) {
+        self.memory.set_buttons(port, buttons);
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn nsf_player(&self) -> Option<&NsfPlayer> {
+        self.nsf_player.as_ref()
+    }
+
+    pub fn nsf_player_mut(&mut self) -> Option<&mut NsfPlayer> {
+        self.nsf_player.as_mut()
+    }
+
+    pub fn switch_disk_side(&mut self) {
+        if let Some(fds) = self.memory.fds_mut() {
+            fds.switch_side();
+        }
+    }
+
+    pub fn flush_save(&mut self) {
+        if let Some(save_path) = &self.save_path
+            && let Err(e) = self.memory.save_prg_ram(save_path)
+        {
+            eprintln!("Failed to write {}: {e}", save_path.display());
+        }
+        if let Some(disk_save_path) = &self.disk_save_path
+            && let Some(fds) = self.memory.fds_mut()
+            && let Err(e) = fds.save_diff(disk_save_path)
+        {
+            eprintln!("Failed to write {}: {e}", disk_save_path.display());
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use crate::controller::{BUTTON_A, BUTTON_START};
+use crate::emulator::Emulator;
+use crate::region::Region;
+use crate::rom_reader::{self, RomError};
+
+// NROM-128 with CHR-RAM running program from $8000
+fn load(program: &[u8]) -> Emulator {
+    let mut file = vec![b'N', b'E', b'S', 0x1A, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    let mut prg_rom = vec![0xEA; 0x4000];
+    prg_rom[..program.len()].copy_from_slice(program);
+    prg_rom[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+    file.extend(prg_rom);
+
+    Emulator::load(rom_reader::parse_rom(&file).unwrap()).unwrap()
+}
+
+#[test]
+fn step_and_reset() {
+    // Strobe the controller, read A then B into $00 and $01, spin
+    let mut emulator = load(&[
+        0xA9, 0x01, 0x8D, 0x16, 0x40, 0xA9, 0x00, 0x8D, 0x16, 0x40, 0xAD, 0x16, 0x40, 0x85, 0x00,
+        0xAD, 0x16, 0x40, 0x85, 0x01, 0x4C, 0x14, 0x80,
+    ]);
+    emulator.set_input(0, BUTTON_A | BUTTON_START);
+
+    emulator.step_instruction().unwrap();
+    assert_eq!(emulator.cpu.program_counter(), 0x8002);
+    for _ in 0..7 {
+        emulator.step_instruction().unwrap();
+    }
+    assert_eq!(emulator.cpu.program_counter(), 0x8014);
+    assert_eq!(emulator.memory.get(0x00), 0x41);
+    assert_eq!(emulator.memory.get(0x01), 0x40);
+
+    // Reset goes through the 7 cycle interrupt sequence, RAM survives
+    emulator.reset();
+    let cpu_cycle = emulator.cpu_cycle;
+    emulator.step_instruction().unwrap();
+    assert_eq!(emulator.cpu.program_counter(), 0x8000);
+    assert_eq!(emulator.cpu_cycle, cpu_cycle + 7);
+    emulator.step_instruction().unwrap();
+    assert_eq!(emulator.cpu.program_counter(), 0x8002);
+    assert_eq!(emulator.cpu_cycle, cpu_cycle + 9);
+    assert_eq!(emulator.memory.get(0x00), 0x41);
+}
+
+#[test]
+fn run_frame() {
+    let mut emulator = load(&[0x4C, 0x00, 0x80]);
+    emulator.run_frame().unwrap();
+    assert_eq!(emulator.next_ppu_clock, 341 * 262 * 4);
+    assert_eq!(emulator.memory.ppu_registers.position(), (0, 0));
+    assert_eq!(emulator.framebuffer().len(), 256 * 240);
+    assert!(emulator.audio_samples().is_empty());
+}
+
+#[test]
+fn clock_ratios() {
+    let mut emulator = load(&[0x4C, 0x00, 0x80]);
+    emulator.set_region(Region::Pal);
+
+    // 3.2 dots per CPU cycle, both land together every 80 master cycles
+    while emulator.master_clock < 80 * 100 {
+        emulator.step().unwrap();
+    }
+    assert_eq!(emulator.master_clock, 80 * 100);
+    assert_eq!(emulator.cpu_cycle - 7, 5 * 100 + 1);
+    assert_eq!(emulator.next_ppu_clock / 5, 16 * 100 + 1);
+}
+
+#[test]
+fn unsupported_opcode() {
+    // JAM
+    let mut emulator = load(&[0x02]);
+    assert_eq!(emulator.step_instruction(), Err("0x02".to_string()));
+    let mut emulator = load(&[0x02]);
+    assert_eq!(emulator.run_frame(), Err("0x02".to_string()));
+}
+
+#[test]
+fn fds_needs_bios() {
+    let mut side = b"\x01*NINTENDO-HVC*".to_vec();
+    side.resize(rom_reader::fds::SIDE_SIZE, 0);
+    let mut rom = rom_reader::parse_rom(&side).unwrap();
+    assert!(matches!(
+        Emulator::load(rom_reader::parse_rom(&side).unwrap()),
+        Err(RomError::MissingBios)
+    ));
+
+    rom.prg_rom = vec![0; 0x1000];
+    assert!(matches!(
+        Emulator::load(rom),
+        Err(RomError::BadBios(0x1000))
+    ));
+}
@@ -0,0 +1,414 @@
+#[cfg(test)]
+mod tests;
+
+use std::path::Path;
+
+use crate::rom_reader::fds::{SIDE_SIZE, block_length};
+use crate::rom_reader::{Mirroring, RomError, ips};
+
+// Lead-in before the first block and the gap after every block, in bytes
+const LEAD_IN: usize = 28300 / 8;
+const BLOCK_GAP: usize = 976 / 8;
+// Room for the gaps on top of the block data
+const RAW_SIDE_SIZE: usize = 68000;
+// CPU cycles per byte at the drive's 96.4 kHz bit rate
+const BYTE_CYCLES: u32 = 149;
+// Time for the head to travel back to the start of the disk
+const REWIND_CYCLES: u32 = 50000;
+// How long the disk stays out of the drive when flipping sides, about a second
+const SWITCH_CYCLES: u32 = 1_789_773;
+
+// Famicom Disk System RAM adapter: timer IRQ and the disk drive behind $4020-$4033
+pub struct Fds {
+    original_sides: Vec<Vec<u8>>,
+    // Sides as the head sees them: lead-in, gaps, start marks and CRCs around every block
+    raw_sides: Vec<Vec<u8>>,
+    side: Option<usize>,
+    next_side: usize,
+    switch_delay: u32,
+    dirty: bool,
+
+    timer_reload: u16,
+    timer_counter: u16,
+    timer_repeat: bool,
+    timer_enabled: bool,
+    timer_irq: bool,
+
+    disk_io_enabled: bool,
+    motor_on: bool,
+    reset_transfer: bool,
+    read_mode: bool,
+    mirroring: Mirroring,
+    crc_control: bool,
+    previous_crc_control: bool,
+    disk_ready: bool,
+    disk_irq_enabled: bool,
+    disk_irq: bool,
+
+    read_data: u8,
+    write_data: u8,
+    transfer_complete: bool,
+    end_of_head: bool,
+    scanning: bool,
+    gap_ended: bool,
+    position: usize,
+    delay: u32,
+    crc: u16,
+}
+
+fn add_gaps(side: &[u8]) -> Vec<u8> {
+    let mut raw = vec![0; LEAD_IN];
+    let mut pointer = 0;
+    let mut file_size = 0;
+    while let Some(&block_type) = side.get(pointer) {
+        let Some(length) = block_length(block_type, file_size) else {
+            break;
+        };
+        let Some(block) = side.get(pointer..pointer + length) else {
+            break;
+        };
+        if block_type == 3 {
+            file_size = u16::from_le_bytes([block[13], block[14]]) as usize;
+        }
+
+        raw.push(0x80);
+        raw.extend_from_slice(block);
+        // Placeholder CRC, CRC errors are never reported so the BIOS doesn't look at it
+        raw.extend_from_slice(&[0x4D, 0x62]);
+        raw.extend(std::iter::repeat_n(0, BLOCK_GAP));
+        pointer += length;
+    }
+    raw.resize(raw.len().max(RAW_SIDE_SIZE), 0);
+
+    raw
+}
+
+fn strip_gaps(raw: &[u8]) -> Vec<u8> {
+    let mut side = Vec::with_capacity(SIDE_SIZE);
+    let mut pointer = 0;
+    let mut file_size = 0;
+    loop {
+        while pointer < raw.len() && raw[pointer] != 0x80 {
+            pointer += 1;
+        }
+        pointer += 1;
+
+        let Some(&block_type) = raw.get(pointer) else {
+            break;
+        };
+        let Some(length) = block_length(block_type, file_size) else {
+            break;
+        };
+        let Some(block) = raw.get(pointer..pointer + length) else {
+            break;
+        };
+        if block_type == 3 {
+            file_size = u16::from_le_bytes([block[13], block[14]]) as usize;
+        }
+
+        side.extend_from_slice(block);
+        pointer += length + 2;
+    }
+    side.resize(SIDE_SIZE, 0);
+
+    side
+}
+
+impl Fds {
+    pub fn new(disk_sides: Vec<Vec<u8>>) -> Fds {
+        Fds {
+            raw_sides: disk_sides.iter().map(|side| add_gaps(side)).collect(),
+            original_sides: disk_sides,
+            side: Some(0),
+            next_side: 0,
+            switch_delay: 0,
+            dirty: false,
+
+            timer_reload: 0,
+            timer_counter: 0,
+            timer_repeat: false,
+            timer_enabled: false,
+            timer_irq: false,
+
+            disk_io_enabled: false,
+            motor_on: false,
+            reset_transfer: false,
+            read_mode: true,
+            mirroring: Mirroring::Horizontal,
+            crc_control: false,
+            previous_crc_control: false,
+            disk_ready: false,
+            disk_irq_enabled: false,
+            disk_irq: false,
+
+            read_data: 0,
+            write_data: 0,
+            transfer_complete: false,
+            end_of_head: true,
+            scanning: false,
+            gap_ended: false,
+            position: 0,
+            delay: 0,
+            crc: 0,
+        }
+    }
+
+    pub fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    pub fn irq(&self) -> bool {
+        self.timer_irq || self.disk_irq
+    }
+
+    // Ejects the disk and inserts the next side after a delay, the BIOS needs to see the drive empty
+    pub fn switch_side(&mut self) {
+        self.next_side = match self.side {
+            Some(side) => side + 1,
+            None => self.next_side + 1,
+        } % self.raw_sides.len();
+        self.side = None;
+        self.switch_delay = SWITCH_CYCLES;
+    }
+
+    fn update_crc(&mut self, value: u8) {
+        for bit in 0..8 {
+            let carry = self.crc & 1 != 0;
+            self.crc >>= 1;
+            if carry {
+                self.crc ^= 0x8408;
+            }
+            if value & (1 << bit) != 0 {
+                self.crc ^= 0x8000;
+            }
+        }
+    }
+
+    pub fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0x4030 => {
+                let mut value = 0;
+                if self.timer_irq {
+                    value |= 0b0000_0001;
+                }
+                if self.transfer_complete {
+                    value |= 0b0000_0010;
+                }
+                if self.end_of_head {
+                    value |= 0b0100_0000;
+                }
+                if self.disk_io_enabled {
+                    value |= 0b1000_0000;
+                }
+                self.timer_irq = false;
+                self.disk_irq = false;
+                self.transfer_complete = false;
+
+                value
+            }
+            0x4031 => {
+                self.transfer_complete = false;
+                self.disk_irq = false;
+
+                self.read_data
+            }
+            0x4032 => {
+                let mut value = 0b0100_0000;
+                if self.side.is_none() {
+                    // Not inserted, not ready, write protected
+                    value |= 0b0000_0111;
+                } else if !self.scanning {
+                    value |= 0b0000_0010;
+                }
+
+                value
+            }
+            // Expansion port, bit 7 is the battery good flag
+            0x4033 => 0b1000_0000,
+            _ => 0,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        if !self.disk_io_enabled && (0x4024..=0x4026).contains(&address) {
+            return;
+        }
+
+        match address {
+            0x4020 => self.timer_reload = (self.timer_reload & 0xFF00) | value as u16,
+            0x4021 => self.timer_reload = (self.timer_reload & 0x00FF) | ((value as u16) << 8),
+            0x4022 => {
+                self.timer_repeat = value & 0b0000_0001 != 0;
+                self.timer_enabled = value & 0b0000_0010 != 0 && self.disk_io_enabled;
+                if self.timer_enabled {
+                    self.timer_counter = self.timer_reload;
+                } else {
+                    self.timer_irq = false;
+                }
+            }
+            0x4023 => {
+                self.disk_io_enabled = value & 0b0000_0001 != 0;
+                if !self.disk_io_enabled {
+                    self.timer_enabled = false;
+                    self.timer_irq = false;
+                    self.disk_irq = false;
+                }
+            }
+            0x4024 => {
+                self.write_data = value;
+                self.transfer_complete = false;
+                self.disk_irq = false;
+            }
+            0x4025 => {
+                self.disk_irq = false;
+                self.motor_on = value & 0b0000_0001 != 0;
+                self.reset_transfer = value & 0b0000_0010 != 0;
+                self.read_mode = value & 0b0000_0100 != 0;
+                self.mirroring = if value & 0b0000_1000 != 0 {
+                    Mirroring::Horizontal
+                } else {
+                    Mirroring::Vertical
+                };
+                self.crc_control = value & 0b0001_0000 != 0;
+                self.disk_ready = value & 0b0100_0000 != 0;
+                self.disk_irq_enabled = value & 0b1000_0000 != 0;
+            }
+            // $4026 expansion port output and $4040+ audio are not emulated
+            _ => {}
+        }
+    }
+
+    // Runs once per CPU cycle
+    pub fn cycle(&mut self) {
+        if self.timer_enabled {
+            if self.timer_counter == 0 {
+                self.timer_irq = true;
+                self.timer_counter = self.timer_reload;
+                if !self.timer_repeat {
+                    self.timer_enabled = false;
+                }
+            } else {
+                self.timer_counter -= 1;
+            }
+        }
+
+        if self.switch_delay > 0 {
+            self.switch_delay -= 1;
+            if self.switch_delay == 0 {
+                self.side = Some(self.next_side);
+            }
+        }
+
+        let Some(side) = self.side else {
+            self.end_of_head = true;
+            self.scanning = false;
+            return;
+        };
+        if !self.disk_io_enabled || !self.motor_on {
+            self.end_of_head = true;
+            self.scanning = false;
+            return;
+        }
+        if self.reset_transfer && !self.scanning {
+            return;
+        }
+        if self.end_of_head {
+            self.delay = REWIND_CYCLES;
+            self.end_of_head = false;
+            self.position = 0;
+            self.gap_ended = false;
+            return;
+        }
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning = true;
+        let mut raise_irq = self.disk_irq_enabled;
+        if self.read_mode {
+            let data = self.raw_sides[side][self.position];
+            if !self.previous_crc_control {
+                self.update_crc(data);
+            }
+            if !self.disk_ready {
+                self.gap_ended = false;
+                self.crc = 0;
+            } else if data != 0 && !self.gap_ended {
+                // The start mark ends the gap, it isn't handed to the BIOS
+                self.gap_ended = true;
+                raise_irq = false;
+            }
+            if self.gap_ended {
+                self.transfer_complete = true;
+                self.read_data = data;
+                if raise_irq {
+                    self.disk_irq = true;
+                }
+            }
+        } else {
+            let mut data = 0;
+            if !self.crc_control {
+                self.transfer_complete = true;
+                data = self.write_data;
+                if raise_irq {
+                    self.disk_irq = true;
+                }
+            }
+            if !self.disk_ready {
+                data = 0;
+            }
+            if !self.crc_control {
+                self.update_crc(data);
+            } else {
+                if !self.previous_crc_control {
+                    self.update_crc(0);
+                    self.update_crc(0);
+                }
+                data = self.crc as u8;
+                self.crc >>= 8;
+            }
+            self.raw_sides[side][self.position] = data;
+            self.dirty = true;
+            self.gap_ended = false;
+        }
+        self.previous_crc_control = self.crc_control;
+
+        self.position += 1;
+        if self.position >= self.raw_sides[side].len() {
+            self.motor_on = false;
+            self.end_of_head = true;
+        } else {
+            self.delay = BYTE_CYCLES;
+        }
+    }
+
+    // Saved games live in an IPS diff against the original image, which is never written to
+    pub fn load_diff(&mut self, path: &Path) -> Result<(), RomError> {
+        let patch = match std::fs::read(path) {
+            Ok(patch) => patch,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(RomError::Io(e)),
+        };
+        let disk = ips::apply(&self.original_sides.concat(), &patch)?;
+        self.raw_sides = disk.chunks(SIDE_SIZE).map(add_gaps).collect();
+        self.dirty = false;
+
+        Ok(())
+    }
+
+    pub fn save_diff(&mut self, path: &Path) -> std::io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let disk: Vec<u8> = self
+            .raw_sides
+            .iter()
+            .flat_map(|raw| strip_gaps(raw))
+            .collect();
+        std::fs::write(path, ips::create(&self.original_sides.concat(), &disk))?;
+        self.dirty = false;
+
+        Ok(())
+    }
+}
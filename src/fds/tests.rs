@@ -0,0 +1,72 @@
+use crate::fds::{Fds, add_gaps, strip_gaps};
+use crate::rom_reader::fds::SIDE_SIZE;
+
+fn disk_side() -> Vec<u8> {
+    let mut side = vec![0x01];
+    side.extend(b"*NINTENDO-HVC*");
+    side.resize(56, 0);
+    side.extend([0x02, 0x01]);
+    let mut file_header = vec![0x03; 16];
+    file_header[13..15].copy_from_slice(&2u16.to_le_bytes());
+    side.extend(file_header);
+    side.extend([0x04, 0x12, 0x34]);
+    side.resize(SIDE_SIZE, 0);
+    side
+}
+
+#[test]
+fn gaps_round_trip() {
+    let side = disk_side();
+    let raw = add_gaps(&side);
+
+    assert!(raw[..28300 / 8].iter().all(|&b| b == 0));
+    assert_eq!(raw[28300 / 8], 0x80);
+    assert_eq!(raw[28300 / 8 + 1], 0x01);
+    assert_eq!(strip_gaps(&raw), side);
+}
+
+#[test]
+fn timer_irq() {
+    let mut fds = Fds::new(vec![disk_side()]);
+    fds.write(0x4023, 0b0000_0001);
+    fds.write(0x4020, 2);
+    fds.write(0x4021, 0);
+    fds.write(0x4022, 0b0000_0010);
+
+    fds.cycle();
+    fds.cycle();
+    assert!(!fds.irq());
+    fds.cycle();
+    assert!(fds.irq());
+
+    // Reading $4030 acknowledges, the timer doesn't repeat
+    assert_eq!(fds.read(0x4030) & 0b0000_0001, 1);
+    assert!(!fds.irq());
+    for _ in 0..10 {
+        fds.cycle();
+    }
+    assert!(!fds.irq());
+}
+
+#[test]
+fn reads_first_block() {
+    let mut fds = Fds::new(vec![disk_side()]);
+    fds.write(0x4023, 0b0000_0001);
+    // Motor on, read mode, then wait for the head to reach the first block
+    fds.write(0x4025, 0b0010_0101);
+    fds.write(0x4025, 0b1110_0101);
+
+    let mut bytes = vec![];
+    for _ in 0..1_000_000 {
+        fds.cycle();
+        if fds.irq() {
+            bytes.push(fds.read(0x4031));
+            if bytes.len() == 15 {
+                break;
+            }
+        }
+    }
+
+    assert_eq!(bytes[0], 0x01);
+    assert_eq!(&bytes[1..15], b"*NINTENDO-HVC*");
+}
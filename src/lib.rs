@@ -0,0 +1,16 @@
+mod checksum;
+pub mod controller;
+pub mod cpu;
+pub mod debug;
+mod emulator;
+pub mod fds;
+pub mod memory;
+pub mod nsf;
+pub mod palette;
+pub mod png;
+pub mod ppu;
+pub mod region;
+pub mod rom_reader;
+
+pub use emulator::Emulator;
+use memory::Memory;
@@ -1,78 +1,556 @@
-mod cpu;
-mod memory;
-mod ppu;
-mod rom_reader;
-use cpu::CPU;
-use memory::Memory;
-use raylib;
+use std::path::Path;
+
+use nemulator::Emulator;
+use nemulator::controller::{
+    BUTTON_A, BUTTON_B, BUTTON_DOWN, BUTTON_LEFT, BUTTON_RIGHT, BUTTON_SELECT, BUTTON_START,
+    BUTTON_UP,
+};
+use nemulator::debug::{
+    self, NAMETABLES_HEIGHT, NAMETABLES_WIDTH, PATTERN_TABLES_HEIGHT, PATTERN_TABLES_WIDTH,
+};
+use nemulator::memory::Memory;
+use nemulator::nsf::NsfPlayer;
+use nemulator::palette::{Palette, PaletteSettings};
+use nemulator::png;
+use nemulator::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH, SPRITES_PER_SCANLINE};
+use nemulator::region::Region;
+use nemulator::rom_reader::{self, HeaderFormat};
 use raylib::prelude::*;
 
-use crate::ppu::{PPURegisters, ppu_cycle};
+// Debug views sit right of the 2x screen
+const DEBUG_X: i32 = SCREEN_WIDTH as i32 * 2;
+// Palette RAM entries in the pattern table view
+const SWATCH_SIZE: i32 = 32;
+// The sprite view lists OAM as 4 columns of 16 entries
+const SPRITE_CELL_WIDTH: i32 = 128;
+const SPRITE_CELL_HEIGHT: i32 = 32;
+// Tall enough for the 2x screen and for the sprite list with its status line below
+const WINDOW_HEIGHT: i32 = 16 * SPRITE_CELL_HEIGHT + 40;
+
+// Controller 1 on the keyboard
+const KEY_BINDINGS: [(KeyboardKey, u8); 8] = [
+    (KeyboardKey::KEY_X, BUTTON_A),
+    (KeyboardKey::KEY_Z, BUTTON_B),
+    (KeyboardKey::KEY_RIGHT_SHIFT, BUTTON_SELECT),
+    (KeyboardKey::KEY_ENTER, BUTTON_START),
+    (KeyboardKey::KEY_UP, BUTTON_UP),
+    (KeyboardKey::KEY_DOWN, BUTTON_DOWN),
+    (KeyboardKey::KEY_LEFT, BUTTON_LEFT),
+    (KeyboardKey::KEY_RIGHT, BUTTON_RIGHT),
+];
 
-struct Emulator {
-    cpu: CPU,
-    memory: Memory,
-    cpu_cycle: u64,
-    ppu_cycle: u64,
+// What the debug panel shows, Tab switches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DebugView {
+    PatternTable,
+    Nametables,
+    Sprites,
 }
 
-impl Emulator {
-    fn cycle(&mut self, d: &mut RaylibDrawHandle) {
-        if self.ppu_cycle % 3 == 0 {
-            self.cpu.cycle(&mut self.memory, self.cpu_cycle).unwrap();
-            self.cpu_cycle += 1;
+impl DebugView {
+    fn next(self) -> DebugView {
+        match self {
+            DebugView::PatternTable => DebugView::Nametables,
+            DebugView::Nametables => DebugView::Sprites,
+            DebugView::Sprites => DebugView::PatternTable,
         }
-        //ppu_cycle(&mut self.memory, self.ppu_cycle, d);
-        self.ppu_cycle += 1;
     }
+}
 
-    fn draw_debug(&self, d: &mut RaylibDrawHandle) {
-        // Draw pattern table
-        for tile_index in 0..256 {
-            for y in 0..8 {
-                let index = tile_index * 16 + y;
-                let pat_lo = self.memory.ppu_get(index);
-                let pat_hi = self.memory.ppu_get(index + 8);
-                for x in 0..8 {
-                    let pixel_color = if (pat_lo & (0b1000_0000 >> x)) != 0 { 1 } else { 0 } + if (pat_hi & (0b1000_0000 >> x)) != 0 { 2 } else { 0 };
-                    let color = Color { r: 85 * pixel_color, g: 85 * pixel_color, b: 85 * pixel_color, a: 255 };
-                    //let color = Color { r: tile_index as u8, g: x * 16, b: y as u8 * 16, a: 255 };
-                    d.draw_rectangle(((tile_index as i32 % 16) * 8 + x as i32 + 341) * 2, (y as i32 + (tile_index as i32 / 16) * 8) * 2, 2, 2, color);
-                }
+// RGBA pixels for a texture, palette indices looked up in the current palette
+fn rgba_pixels(indices: &[u16], palette: &Palette) -> Vec<u8> {
+    indices
+        .iter()
+        .flat_map(|&index| {
+            let [r, g, b] = palette.rgb(index);
+            [r, g, b, 255]
+        })
+        .collect()
+}
+
+fn draw_nsf_player(d: &mut RaylibDrawHandle, nsf_player: &NsfPlayer) {
+    let info = &nsf_player.info;
+
+    d.clear_background(Color::BLACK);
+    d.draw_text(&info.title, 20, 20, 30, Color::WHITE);
+    d.draw_text(&info.artist, 20, 60, 20, Color::GRAY);
+    d.draw_text(&info.copyright, 20, 85, 20, Color::GRAY);
+    // INIT and PLAY run, but nothing turns the register writes into sound yet
+    d.draw_text(
+        "No sound: the APU isn't emulated yet",
+        DEBUG_X,
+        20,
+        20,
+        Color::ORANGE,
+    );
+    let chips = info.expansion_chip_names();
+    if !chips.is_empty() {
+        d.draw_text(
+            &format!("Expansion audio: {}", chips.join(", ")),
+            20,
+            110,
+            20,
+            Color::GRAY,
+        );
+    }
+
+    let elapsed = nsf_player.elapsed_seconds() as u32;
+    let length = info
+        .track_times
+        .get(nsf_player.song() as usize)
+        .copied()
+        .flatten()
+        .map(|ms| format!(" / {}:{:02}", ms / 60000, ms / 1000 % 60))
+        .unwrap_or_default();
+    d.draw_text(
+        &format!(
+            "{}:{:02}{length}    Left/Right: previous/next track",
+            elapsed / 60,
+            elapsed % 60
+        ),
+        20,
+        145,
+        20,
+        Color::WHITE,
+    );
+
+    // Keep the current track in view
+    let first = (nsf_player.song() as i32 - 8).max(0);
+    for (row, song) in (first..info.songs as i32).take(16).enumerate() {
+        let label = info
+            .track_labels
+            .get(song as usize)
+            .filter(|label| !label.is_empty())
+            .cloned()
+            .unwrap_or_else(|| format!("Track {}", song + 1));
+        let color = if song == nsf_player.song() as i32 {
+            Color::YELLOW
+        } else {
+            Color::GRAY
+        };
+        d.draw_text(
+            &format!("{:3}  {label}", song + 1),
+            20,
+            185 + row as i32 * 22,
+            20,
+            color,
+        );
+    }
+}
+
+fn blank_texture(
+    rl: &mut RaylibHandle,
+    thread: &RaylibThread,
+    width: usize,
+    height: usize,
+) -> Texture2D {
+    rl.load_texture_from_image(
+        thread,
+        &Image::gen_image_color(width as i32, height as i32, Color::BLACK),
+    )
+    .expect("Failed to create a texture")
+}
+
+// Splits start..start + length into the pieces left after wrapping around 0..size
+fn wrapped_spans(start: i32, length: i32, size: i32) -> Vec<(i32, i32)> {
+    let first = length.min(size - start);
+    let mut spans = vec![(start, first)];
+    if first < length {
+        spans.push((0, length - first));
+    }
+
+    spans
+}
+
+fn draw_nametables(
+    d: &mut RaylibDrawHandle,
+    memory: &Memory,
+    texture: &mut Texture2D,
+    palette: &Palette,
+) {
+    texture
+        .update_texture(&rgba_pixels(&debug::nametables(memory), palette))
+        .expect("Nametable image size matches its texture");
+    d.draw_texture_ex(
+        &*texture,
+        Vector2::new(DEBUG_X as f32, 0.0),
+        0.0,
+        1.0,
+        Color::WHITE,
+    );
+
+    // The part the next frame starts showing, wrapping around like the scroll does
+    let (width, height) = (NAMETABLES_WIDTH as i32, NAMETABLES_HEIGHT as i32);
+    let (scroll_x, scroll_y) = memory.ppu_registers.scroll_origin();
+    let (left, top) = (scroll_x as i32 % width, scroll_y as i32 % height);
+    let right = (left + SCREEN_WIDTH as i32 - 1) % width;
+    let bottom = (top + SCREEN_HEIGHT as i32 - 1) % height;
+    for (y, length) in wrapped_spans(top, SCREEN_HEIGHT as i32, height) {
+        d.draw_rectangle(DEBUG_X + left, y, 1, length, Color::RED);
+        d.draw_rectangle(DEBUG_X + right, y, 1, length, Color::RED);
+    }
+    for (x, length) in wrapped_spans(left, SCREEN_WIDTH as i32, width) {
+        d.draw_rectangle(DEBUG_X + x, top, length, 1, Color::RED);
+        d.draw_rectangle(DEBUG_X + x, bottom, length, 1, Color::RED);
+    }
+
+    let mouse = d.get_mouse_position();
+    let (x, y) = (mouse.x as i32 - DEBUG_X, mouse.y as i32);
+    if (0..width).contains(&x) && (0..height).contains(&y) {
+        let tile = debug::nametable_tile(memory, x as usize, y as usize);
+        d.draw_text(
+            &format!(
+                "${:04X}: tile ${:02X}    ${:04X}: attribute ${:02X}, palette {}",
+                tile.address, tile.tile, tile.attribute_address, tile.attribute, tile.palette
+            ),
+            DEBUG_X,
+            height + 10,
+            20,
+            Color::WHITE,
+        );
+    }
+}
+
+// Both pattern tables with the chosen palette and all of palette RAM below them
+fn draw_pattern_tables(
+    d: &mut RaylibDrawHandle,
+    memory: &Memory,
+    texture: &mut Texture2D,
+    palette: &Palette,
+    pattern_palette: u8,
+) {
+    texture
+        .update_texture(&rgba_pixels(
+            &debug::pattern_tables(memory, pattern_palette),
+            palette,
+        ))
+        .expect("Pattern table image size matches its texture");
+    d.draw_texture_ex(
+        &*texture,
+        Vector2::new(DEBUG_X as f32, 0.0),
+        0.0,
+        2.0,
+        Color::WHITE,
+    );
+
+    // Background palettes on the first row, sprite palettes on the second
+    let top = PATTERN_TABLES_HEIGHT as i32 * 2 + 10;
+    for entry in 0..32 {
+        let value = memory.ppu_get(0x3F00 + entry) & 0x3F;
+        let [r, g, b] = palette.rgb(value as u16);
+        let x = DEBUG_X + (entry as i32 % 16) * SWATCH_SIZE;
+        let y = top + (entry as i32 / 16) * SWATCH_SIZE;
+        d.draw_rectangle(x, y, SWATCH_SIZE, SWATCH_SIZE, Color::new(r, g, b, 255));
+        let text_color = if r as u16 + g as u16 + b as u16 > 384 {
+            Color::BLACK
+        } else {
+            Color::WHITE
+        };
+        d.draw_text(&format!("{value:02X}"), x + 9, y + 11, 10, text_color);
+    }
+    d.draw_rectangle_lines(
+        DEBUG_X + (pattern_palette as i32 % 4) * 4 * SWATCH_SIZE,
+        top + (pattern_palette as i32 / 4) * SWATCH_SIZE,
+        4 * SWATCH_SIZE,
+        SWATCH_SIZE,
+        Color::YELLOW,
+    );
+    d.draw_text(
+        "[ ]: palette    P: export PNG",
+        DEBUG_X,
+        top + 2 * SWATCH_SIZE + 10,
+        20,
+        Color::GRAY,
+    );
+}
+
+// All 64 OAM entries drawn at 2x, outlined when they cover the screen line under the mouse.
+// Frames run whole, so the PPU's own scanline is always 0 by the time this draws
+fn draw_sprites(d: &mut RaylibDrawHandle, memory: &Memory, palette: &Palette, line: Option<u16>) {
+    let on_line = line.map_or_else(Vec::new, |line| debug::sprites_on_line(memory, line));
+    for (index, sprite) in debug::sprites(memory).iter().enumerate() {
+        let x = DEBUG_X + (index as i32 / 16) * SPRITE_CELL_WIDTH;
+        let y = (index as i32 % 16) * SPRITE_CELL_HEIGHT;
+        for (pixel_index, &pixel) in debug::sprite_pixels(memory, sprite).iter().enumerate() {
+            if pixel == 0 {
+                continue;
             }
+            let [r, g, b] = palette.rgb(debug::palette_color(memory, sprite.palette(), pixel));
+            d.draw_rectangle(
+                x + (pixel_index as i32 % 8) * 2,
+                y + (pixel_index as i32 / 8) * 2,
+                2,
+                2,
+                Color::new(r, g, b, 255),
+            );
+        }
+        d.draw_text(
+            &format!("{index:2}  X {:3}  Y {:3}", sprite.x, sprite.y),
+            x + 24,
+            y + 4,
+            10,
+            Color::WHITE,
+        );
+        d.draw_text(
+            &format!(
+                "T {:02X}  A {:02X}  P{}",
+                sprite.tile,
+                sprite.attributes,
+                sprite.palette()
+            ),
+            x + 24,
+            y + 18,
+            10,
+            Color::GRAY,
+        );
+        // Evaluation only keeps the first eight it finds
+        if let Some(position) = on_line.iter().position(|&found| found == index) {
+            let color = if position < SPRITES_PER_SCANLINE {
+                Color::GREEN
+            } else {
+                Color::RED
+            };
+            d.draw_rectangle_lines(x, y, SPRITE_CELL_WIDTH, SPRITE_CELL_HEIGHT, color);
         }
     }
+
+    let text = match line {
+        Some(line) => {
+            d.draw_rectangle(0, line as i32 * 2, DEBUG_X, 2, Color::new(255, 255, 0, 128));
+            format!(
+                "Hovered line {line}: {} sprites, {} dropped",
+                on_line.len(),
+                on_line.len().saturating_sub(SPRITES_PER_SCANLINE)
+            )
+        }
+        None => String::from("Hover the screen to pick a line"),
+    };
+    d.draw_text(
+        &text,
+        DEBUG_X,
+        16 * SPRITE_CELL_HEIGHT + 10,
+        20,
+        Color::GRAY,
+    );
+}
+
+fn export_pattern_tables(memory: &Memory, palette: &Palette, pattern_palette: u8, path: &Path) {
+    let rgb: Vec<u8> = debug::pattern_tables(memory, pattern_palette)
+        .iter()
+        .flat_map(|&index| palette.rgb(index))
+        .collect();
+    let image = png::encode_rgb(PATTERN_TABLES_WIDTH, PATTERN_TABLES_HEIGHT, &rgb);
+    match std::fs::write(path, image) {
+        Ok(()) => eprintln!("Saved the pattern tables to {}", path.display()),
+        Err(e) => eprintln!("Failed to write {}: {e}", path.display()),
+    }
 }
 
 fn main() {
-    let file = rom_reader::read_file("./assets/tests/nestest.nes");
-    let mut memory = Memory::new(
-        vec![0; 0x800],
-        PPURegisters::new(),
-        [0; 32],
-        file.prg_rom,
-        file.chr_rom,
-    );
+    let mut rom_path = None;
+    let mut fds_bios_path = String::from("./assets/disksys.rom");
+    let mut patch_path = None;
+    let mut entry_name = None;
+    let mut palette_path = None;
+    let mut region = None;
+    // Any of the knobs switches from the built-in table to the generated palette
+    let mut palette_settings: Option<PaletteSettings> = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--fds-bios" => fds_bios_path = args.next().expect("--fds-bios needs a path"),
+            "--patch" => patch_path = Some(args.next().expect("--patch needs a path")),
+            "--entry" => entry_name = Some(args.next().expect("--entry needs a name")),
+            "--palette" => palette_path = Some(args.next().expect("--palette needs a path")),
+            "--region" => {
+                region = match args.next().as_deref() {
+                    Some("ntsc") => Some(Region::Ntsc),
+                    Some("pal") => Some(Region::Pal),
+                    Some("dendy") => Some(Region::Dendy),
+                    _ => panic!("--region needs ntsc, pal or dendy"),
+                }
+            }
+            "--hue" | "--saturation" | "--contrast" | "--brightness" => {
+                let value: f64 = args
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or_else(|| panic!("{arg} needs a number"));
+                let settings = palette_settings.get_or_insert_with(PaletteSettings::default);
+                match arg.as_str() {
+                    "--hue" => settings.hue = value,
+                    "--saturation" => settings.saturation = value,
+                    "--contrast" => settings.contrast = value,
+                    _ => settings.brightness = value,
+                }
+            }
+            _ => rom_path = Some(arg),
+        }
+    }
+    let Some(rom_path) = rom_path else {
+        eprintln!("Usage: nemulator [options] <rom>");
+        std::process::exit(1);
+    };
+
+    // Read once, both the entry listing and the loader work on the bytes
+    let bytes = rom_reader::read_bytes(Path::new(&rom_path)).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(1);
+    });
+    // Archives holding several ROMs load the first one unless told otherwise
+    if entry_name.is_none() {
+        let entries = rom_reader::archive_entries(&bytes).unwrap_or_default();
+        if entries.len() > 1 {
+            eprintln!("{rom_path} holds several ROMs, loading {}", entries[0]);
+            eprintln!("Pick another with --entry <name>:");
+            for entry in &entries {
+                eprintln!("  {entry}");
+            }
+        }
+    }
+    let mut file = rom_reader::load_file(
+        &rom_path,
+        bytes,
+        patch_path.as_deref(),
+        entry_name.as_deref(),
+    )
+    .unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(1);
+    });
+    if file.header.format == HeaderFormat::Fds
+        && let Err(e) = rom_reader::fds::load_bios(&mut file, &fds_bios_path)
+    {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+
+    let mut emulator = Emulator::load(file).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(1);
+    });
+    emulator.load_save_files(Path::new(&rom_path));
+    // The command line wins over the header
+    if let Some(region) = region {
+        emulator.set_region(region);
+    }
 
-    let mut emulator = Emulator {
-        cpu: CPU::new(&mut memory, None),
-        memory,
-        cpu_cycle: 7,
-        ppu_cycle: 0,
+    let palette = match (&palette_path, &palette_settings) {
+        (Some(path), _) => Palette::load(Path::new(path)).unwrap_or_else(|e| {
+            eprintln!("Failed to read palette: {e}");
+            std::process::exit(1);
+        }),
+        (None, Some(settings)) => Palette::generate(settings),
+        (None, None) if emulator.region() == Region::Ntsc => Palette::ntsc(),
+        (None, None) => Palette::pal(),
     };
 
+    let rom_name = Path::new(&rom_path)
+        .file_name()
+        .map_or(rom_path.clone(), |name| name.to_string_lossy().into_owned());
+    let title = match &entry_name {
+        Some(entry) => format!("nemulator - {rom_name} ({entry})"),
+        None => format!("nemulator - {rom_name}"),
+    };
     let (mut rl, thread) = raylib::init()
-        .size((341 + 8*16) * 2, 262 * 2)
-        .title("Hello, World")
+        .size(DEBUG_X + NAMETABLES_WIDTH as i32, WINDOW_HEIGHT)
+        .title(&title)
         .build();
 
-    rl.set_target_fps(60);
+    // 50 Hz consoles run their frames a sixth slower
+    rl.set_target_fps(match emulator.region() {
+        Region::Ntsc => 60,
+        Region::Pal | Region::Dendy => 50,
+    });
+
+    // The PPU only fills the framebuffer, it reaches the window as a texture once per frame
+    let mut screen = blank_texture(&mut rl, &thread, SCREEN_WIDTH, SCREEN_HEIGHT);
+    let mut nametable_texture =
+        blank_texture(&mut rl, &thread, NAMETABLES_WIDTH, NAMETABLES_HEIGHT);
+    let mut pattern_texture = blank_texture(
+        &mut rl,
+        &thread,
+        PATTERN_TABLES_WIDTH,
+        PATTERN_TABLES_HEIGHT,
+    );
+    let mut debug_view = DebugView::PatternTable;
+    // 0-3 background, 4-7 sprite palettes
+    let mut pattern_palette = 0;
 
     while !rl.window_should_close() {
+        if rl.is_key_pressed(KeyboardKey::KEY_S) {
+            emulator.switch_disk_side();
+        }
+        if rl.is_key_pressed(KeyboardKey::KEY_R) {
+            emulator.reset();
+        }
+        if rl.is_key_pressed(KeyboardKey::KEY_TAB) {
+            debug_view = debug_view.next();
+        }
+        // Screen line under the mouse for the sprite view
+        let mouse = rl.get_mouse_position();
+        let sprite_line = if (0..DEBUG_X).contains(&(mouse.x as i32))
+            && (0..SCREEN_HEIGHT as i32 * 2).contains(&(mouse.y as i32))
+        {
+            Some(mouse.y as u16 / 2)
+        } else {
+            None
+        };
+        if rl.is_key_pressed(KeyboardKey::KEY_LEFT_BRACKET) {
+            pattern_palette = (pattern_palette + 7) % 8;
+        }
+        if rl.is_key_pressed(KeyboardKey::KEY_RIGHT_BRACKET) {
+            pattern_palette = (pattern_palette + 1) % 8;
+        }
+        if rl.is_key_pressed(KeyboardKey::KEY_P) {
+            export_pattern_tables(
+                emulator.memory(),
+                &palette,
+                pattern_palette,
+                &Path::new(&rom_path).with_extension("chr.png"),
+            );
+        }
+        if let Some(nsf_player) = emulator.nsf_player_mut() {
+            if rl.is_key_pressed(KeyboardKey::KEY_RIGHT) {
+                nsf_player.next_song();
+            }
+            if rl.is_key_pressed(KeyboardKey::KEY_LEFT) {
+                nsf_player.previous_song();
+            }
+        }
+        let buttons = KEY_BINDINGS
+            .iter()
+            .filter(|(key, _)| rl.is_key_down(*key))
+            .fold(0, |buttons, (_, button)| buttons | button);
+        emulator.set_input(0, buttons);
+
+        if let Err(opcode) = emulator.run_frame() {
+            eprintln!("The CPU stopped on opcode {opcode}, which isn't emulated");
+            break;
+        }
+        screen
+            .update_texture(&rgba_pixels(emulator.framebuffer(), &palette))
+            .expect("Framebuffer size matches the screen texture");
+
         let mut d = rl.begin_drawing(&thread);
-        for _ in 0..(341 * 262) {
-            emulator.cycle(&mut d);
+        d.clear_background(Color::BLACK);
+        d.draw_texture_ex(&screen, Vector2::new(0.0, 0.0), 0.0, 2.0, Color::WHITE);
+        match (emulator.nsf_player(), debug_view) {
+            (Some(nsf_player), _) => draw_nsf_player(&mut d, nsf_player),
+            (None, DebugView::PatternTable) => draw_pattern_tables(
+                &mut d,
+                emulator.memory(),
+                &mut pattern_texture,
+                &palette,
+                pattern_palette,
+            ),
+            (None, DebugView::Nametables) => {
+                draw_nametables(&mut d, emulator.memory(), &mut nametable_texture, &palette)
+            }
+            (None, DebugView::Sprites) => {
+                draw_sprites(&mut d, emulator.memory(), &palette, sprite_line)
+            }
         }
-        emulator.draw_debug(&mut d);
     }
+
+    emulator.flush_save();
 }
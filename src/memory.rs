@@ -1,4 +1,13 @@
+use std::path::Path;
+
+use crate::controller::Controller;
+use crate::fds::Fds;
+use crate::nsf;
 use crate::ppu::PPURegisters;
+use crate::rom_reader::Mirroring;
+
+#[cfg(test)]
+mod tests;
 
 pub struct Memory {
     ram: Vec<u8>,
@@ -6,8 +15,19 @@ pub struct Memory {
     apu_io: [u8; 32],
     prg_rom: Vec<u8>,
     chr_rom: Vec<u8>,
+    chr_ram: Vec<u8>,
+    prg_ram: Vec<u8>,
+    prg_ram_dirty: bool,
+    // Set by a $4014 write, the CPU picks it up to stall for the transfer
+    oam_dma_pending: bool,
     vram: Vec<u8>,
     palettes: Vec<u8>,
+    mirroring: Mirroring,
+    // Read through $4016 and $4017, strobed together by $4016 writes
+    controllers: [Controller; 2],
+    fds: Option<Fds>,
+    // NSF bankswitching, 4 KiB PRG-ROM banks for $8000-$FFFF set through $5FF8-$5FFF
+    prg_banks: Option<[u8; 8]>,
 }
 
 impl Memory {
@@ -17,6 +37,8 @@ impl Memory {
         apu_io: [u8; 32],
         prg_rom: Vec<u8>,
         chr_rom: Vec<u8>,
+        chr_ram: Vec<u8>,
+        prg_ram: Vec<u8>,
     ) -> Memory {
         Memory {
             ram,
@@ -24,27 +46,218 @@ impl Memory {
             apu_io,
             prg_rom,
             chr_rom,
-            vram: vec![0; 2048],
+            chr_ram,
+            prg_ram,
+            prg_ram_dirty: false,
+            oam_dma_pending: false,
+            // Four-screen carts add 2 KiB of their own to the console's 2 KiB
+            vram: vec![0; 4096],
             palettes: vec![0; 32],
+            mirroring: Mirroring::Horizontal,
+            controllers: [Controller::default(); 2],
+            fds: None,
+            prg_banks: None,
         }
     }
 
+    // Test cartridges start out as just PRG-ROM with 8 KiB of CHR-RAM
+    #[cfg(test)]
+    pub(crate) fn with_prg(prg_rom: Vec<u8>) -> Memory {
+        Memory::new(
+            vec![0; 0x800],
+            PPURegisters::new(),
+            [0; 32],
+            prg_rom,
+            vec![],
+            vec![0; 0x2000],
+            vec![],
+        )
+    }
+
+    // Replaces the CHR-RAM, carts with CHR-ROM have none
+    #[cfg(test)]
+    pub(crate) fn with_chr_rom(mut self, chr_rom: Vec<u8>) -> Memory {
+        self.chr_rom = chr_rom;
+        self.chr_ram = vec![];
+        self
+    }
+
+    #[cfg(test)]
+    pub(crate) fn with_chr_ram(mut self, size: usize) -> Memory {
+        self.chr_ram = vec![0; size];
+        self
+    }
+
+    #[cfg(test)]
+    pub(crate) fn with_prg_ram(mut self, size: usize) -> Memory {
+        self.prg_ram = vec![0; size];
+        self
+    }
+
+    // Soldered nametable mirroring from the header, mappers can change it at runtime
+    pub fn set_mirroring(&mut self, mirroring: Mirroring) {
+        self.mirroring = mirroring;
+    }
+
+    // FDS images run the BIOS as PRG-ROM with 32 KiB of PRG-RAM at $6000-$DFFF
+    pub fn attach_fds(&mut self, fds: Fds) {
+        self.fds = Some(fds);
+    }
+
+    // Power-up state an NSF expects before every INIT call
+    pub fn reset_nsf(&mut self, banks: [u8; 8]) {
+        self.ram.fill(0);
+        self.prg_ram.fill(0);
+        self.prg_banks = Some(banks);
+        for address in 0x4000..=0x4013 {
+            self.set(address, 0x00);
+        }
+        self.set(0x4015, 0x00);
+        self.set(0x4015, 0x0F);
+        self.set(0x4017, 0x40);
+    }
+
+    pub fn fds_mut(&mut self) -> Option<&mut Fds> {
+        self.fds.as_mut()
+    }
+
+    // Clocks the cartridge hardware, once per CPU cycle
+    pub fn tick(&mut self) {
+        if let Some(fds) = &mut self.fds {
+            fds.cycle();
+        }
+    }
+
+    pub fn set_buttons(&mut self, port: usize, buttons: u8) {
+        self.controllers[port].set_buttons(buttons);
+    }
+
+    pub fn take_oam_dma(&mut self) -> bool {
+        std::mem::take(&mut self.oam_dma_pending)
+    }
+
+    // Copies a whole CPU page into OAM starting at OAMADDR, same as 256 $2004 writes
+    fn oam_dma(&mut self, page: u8) {
+        for low in 0..=0xFF {
+            let value = self.get(u16::from_le_bytes([low, page]));
+            self.ppu_registers.set(0x2004, value);
+        }
+        self.oam_dma_pending = true;
+    }
+
+    pub fn nmi(&self) -> bool {
+        self.ppu_registers.nmi_output()
+    }
+
+    pub fn irq(&self) -> bool {
+        self.fds.as_ref().is_some_and(|fds| fds.irq())
+    }
+
     pub fn get(&mut self, address: u16) -> u8 {
         match address {
             0x0000..=0x1FFF => self.ram[(address & 0x07FF) as usize],
+            0x2000..=0x3FFF if address & 0x0007 == 7 => {
+                let ppu_address = self.ppu_registers.data_address();
+                let value = self.ppu_get(ppu_address);
+                // $3F00-$3FFF sits on top of the $2F00-$2FFF nametable mirror
+                let underneath = if ppu_address >= 0x3F00 {
+                    self.ppu_get(ppu_address - 0x1000)
+                } else {
+                    value
+                };
+                self.ppu_registers.read_data(value, underneath)
+            }
             0x2000..=0x3FFF => self.ppu_registers.get(address & 0x0007),
+            // Only bit 0 is driven, the upper bits keep the $40 of the address on the bus
+            0x4016 | 0x4017 => 0x40 | self.controllers[(address - 0x4016) as usize].read(),
             0x4000..=0x401F => self.apu_io[(address - 0x4000) as usize],
-            0x8000..=0xFFFF => self.prg_rom[(address - 0x8000) as usize % self.prg_rom.len()],
-            _ => panic!("Invalid address: {:X}", address),
+            0x4020..=0x5FFF => match &mut self.fds {
+                Some(fds) => fds.read(address),
+                None if self.prg_banks.is_some() => nsf::stub_read(address),
+                // Nothing answers on a plain cartridge, the high byte of the address is left on the bus
+                None => (address >> 8) as u8,
+            },
+            0x6000..=0xDFFF if self.fds.is_some() => self.prg_ram[(address - 0x6000) as usize],
+            0x6000..=0x7FFF => {
+                if self.prg_ram.is_empty() {
+                    return 0;
+                }
+                self.prg_ram[(address - 0x6000) as usize % self.prg_ram.len()]
+            }
+            // No PRG-ROM, like an FDS image still waiting for its BIOS, leaves the bus floating
+            0x8000..=0xFFFF if self.prg_rom.is_empty() => (address >> 8) as u8,
+            0x8000..=0xFFFF => match self.prg_banks {
+                Some(banks) => {
+                    let bank = banks[((address - 0x8000) >> 12) as usize] as usize;
+                    self.prg_rom[(bank * 0x1000 + (address & 0x0FFF) as usize) % self.prg_rom.len()]
+                }
+                None => self.prg_rom[(address - 0x8000) as usize % self.prg_rom.len()],
+            },
+        }
+    }
+
+    // Maps $2000-$2FFF onto the 1 KiB nametables actually present, the FDS picks its own
+    fn nametable_index(&self, address: u16) -> usize {
+        let mirroring = self
+            .fds
+            .as_ref()
+            .map_or(self.mirroring, |fds| fds.mirroring());
+        let table = (address as usize >> 10) & 0b11;
+        let bank = match mirroring {
+            Mirroring::Horizontal => table >> 1,
+            Mirroring::Vertical => table & 1,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+            Mirroring::FourScreen => table,
+        };
+
+        bank * 0x400 + (address as usize & 0x03FF)
+    }
+
+    // $3F10, $3F14, $3F18 and $3F1C are the backdrop entries of the background palettes
+    fn palette_index(address: u16) -> usize {
+        let index = address as usize & 0b0001_1111;
+        if index & 0b0001_0011 == 0b0001_0000 {
+            index & 0b0000_1111
+        } else {
+            index
         }
     }
 
     pub fn ppu_get(&self, address: u16) -> u8 {
         let address = address % 0x4000;
         match address {
-            0x0000..=0x1FFF => self.chr_rom[address as usize],
-            0x2000..=0x3EFF => self.vram[((address - 0x2000) % 2048) as usize],
-            0x3F00..=0x3FFF => self.palettes[(address & 0b0001_1111) as usize],
+            // Without CHR-ROM or CHR-RAM nothing drives the pattern bus
+            0x0000..=0x1FFF => {
+                if !self.chr_rom.is_empty() {
+                    self.chr_rom[address as usize % self.chr_rom.len()]
+                } else if !self.chr_ram.is_empty() {
+                    self.chr_ram[address as usize % self.chr_ram.len()]
+                } else {
+                    0
+                }
+            }
+            0x2000..=0x3EFF => self.vram[self.nametable_index(address)],
+            0x3F00..=0x3FFF => self.palettes[Memory::palette_index(address)],
+            _ => panic!("Invalid ppu address: {:X}", address),
+        }
+    }
+
+    pub fn ppu_set(&mut self, address: u16, value: u8) {
+        let address = address % 0x4000;
+        match address {
+            // CHR-ROM carts have no CHR-RAM, the write goes nowhere
+            0x0000..=0x1FFF => {
+                if self.chr_rom.is_empty() && !self.chr_ram.is_empty() {
+                    let index = address as usize % self.chr_ram.len();
+                    self.chr_ram[index] = value;
+                }
+            }
+            0x2000..=0x3EFF => {
+                let index = self.nametable_index(address);
+                self.vram[index] = value;
+            }
+            0x3F00..=0x3FFF => self.palettes[Memory::palette_index(address)] = value,
             _ => panic!("Invalid ppu address: {:X}", address),
         }
     }
@@ -53,12 +266,74 @@ impl Memory {
         match address {
             0x0000..=0x1FFF => self.ram[(address & 0x07FF) as usize] = value,
             0x2000..=0x3FFF => {
-                self.ppu_registers
-                    .set(address, value, &mut self.vram, &mut self.palettes)
+                if address & 0x0007 == 7 {
+                    self.ppu_set(self.ppu_registers.data_address(), value);
+                }
+                self.ppu_registers.set(address, value);
+            }
+            0x4014 => self.oam_dma(value),
+            0x4016 => {
+                for controller in &mut self.controllers {
+                    controller.set_strobe(value & 1 != 0);
+                }
             }
             0x4000..=0x401F => self.apu_io[(address - 0x4000) as usize] = value,
+            0x4020..=0x5FFF => match (&mut self.fds, &mut self.prg_banks) {
+                (Some(fds), _) => fds.write(address, value),
+                (None, Some(banks)) => {
+                    if address >= 0x5FF8 {
+                        banks[(address - 0x5FF8) as usize] = value;
+                    }
+                }
+                (None, None) => {}
+            },
+            0x6000..=0xDFFF if self.fds.is_some() => {
+                self.prg_ram[(address - 0x6000) as usize] = value
+            }
+            0x6000..=0x7FFF => {
+                if self.prg_ram.is_empty() {
+                    return;
+                }
+                let index = (address - 0x6000) as usize % self.prg_ram.len();
+                if self.prg_ram[index] != value {
+                    self.prg_ram[index] = value;
+                    self.prg_ram_dirty = true;
+                }
+            }
             0x8000..=0xFFFF => {}
-            _ => panic!("Invalid address: {:X}", address),
         };
     }
+
+    // Trainers are copied to $7000-$71FF before the game starts, like the copiers did
+    pub fn load_trainer(&mut self, trainer: &[u8]) {
+        if self.prg_ram.len() < 0x2000 {
+            self.prg_ram.resize(0x2000, 0);
+        }
+        self.prg_ram[0x1000..0x1000 + trainer.len()].copy_from_slice(trainer);
+    }
+
+    // Restores battery-backed PRG-RAM, a missing .sav just means a fresh cartridge
+    pub fn load_prg_ram(&mut self, path: &Path) -> std::io::Result<()> {
+        let save = match std::fs::read(path) {
+            Ok(save) => save,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let len = save.len().min(self.prg_ram.len());
+        self.prg_ram[..len].copy_from_slice(&save[..len]);
+        self.prg_ram_dirty = false;
+
+        Ok(())
+    }
+
+    // Writes PRG-RAM out only if the game touched it since the last flush
+    pub fn save_prg_ram(&mut self, path: &Path) -> std::io::Result<()> {
+        if !self.prg_ram_dirty {
+            return Ok(());
+        }
+        std::fs::write(path, &self.prg_ram)?;
+        self.prg_ram_dirty = false;
+
+        Ok(())
+    }
 }
@@ -0,0 +1,172 @@
+use crate::cpu::CPU;
+use crate::memory::Memory;
+
+#[test]
+fn oam_dma() {
+    // LDA #$02, STA $4014, NOP
+    let mut prg_rom = vec![0xEA; 0x8000];
+    prg_rom[0..5].copy_from_slice(&[0xA9, 0x02, 0x8D, 0x14, 0x40]);
+    prg_rom[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+    let mut memory = Memory::with_prg(prg_rom).with_chr_rom(vec![0; 0x2000]);
+    for i in 0..=0xFF {
+        memory.set(0x0200 + i, i as u8);
+    }
+    memory.set(0x2003, 0x10);
+
+    let mut cpu = CPU::new(&mut memory, None);
+    let mut boundaries = Vec::new();
+    for emulator_cycle in 7..600 {
+        if cpu.at_instruction_boundary(emulator_cycle) {
+            boundaries.push(emulator_cycle);
+        }
+        cpu.cycle(&mut memory, emulator_cycle).unwrap();
+    }
+
+    // The STA lands on cycle 12, even, so the NOP waits 513 cycles past its usual start at 13
+    // and still takes its own 2 cycles afterwards
+    assert_eq!(boundaries[..4], [7, 9, 13 + 513, 13 + 513 + 2]);
+
+    // The copy starts at OAMADDR and wraps around
+    memory.set(0x2003, 0x10);
+    assert_eq!(memory.get(0x2004), 0x00);
+    memory.set(0x2003, 0x0F);
+    assert_eq!(memory.get(0x2004), 0xFF);
+}
+
+#[test]
+fn oam_dma_cycles() {
+    // LDA $00, LDA #$02, STA $4014, NOP, NOP
+    let mut prg_rom = vec![0xEA; 0x8000];
+    prg_rom[0..7].copy_from_slice(&[0xA5, 0x00, 0xA9, 0x02, 0x8D, 0x14, 0x40]);
+    prg_rom[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+    let mut memory = Memory::with_prg(prg_rom).with_chr_rom(vec![0; 0x2000]);
+
+    let mut cpu = CPU::new(&mut memory, None);
+    let mut boundaries = Vec::new();
+    for emulator_cycle in 7..600 {
+        if cpu.at_instruction_boundary(emulator_cycle) {
+            boundaries.push((emulator_cycle, cpu.program_counter()));
+        }
+        cpu.cycle(&mut memory, emulator_cycle).unwrap();
+    }
+
+    // 3 + 2 + 4 cycles, the STA lands on cycle 15, odd, so the DMA takes 514, then 2 per NOP
+    assert_eq!(
+        boundaries[..6],
+        [
+            (7, 0x8000),
+            (10, 0x8002),
+            (12, 0x8004),
+            (16 + 514, 0x8007),
+            (16 + 514 + 2, 0x8008),
+            (16 + 514 + 4, 0x8009),
+        ]
+    );
+}
+
+#[test]
+fn ppudata() {
+    let mut memory = Memory::with_prg(vec![0; 0x4000]);
+    let write = |memory: &mut Memory, address: u16, values: &[u8]| {
+        memory.set(0x2006, (address >> 8) as u8);
+        memory.set(0x2006, address as u8);
+        for &value in values {
+            memory.set(0x2007, value);
+        }
+    };
+    write(&mut memory, 0x2F00, &[0xAA]);
+    write(&mut memory, 0x3F00, &[0x0F, 0x21]);
+    write(&mut memory, 0x3F14, &[0x30]);
+    write(&mut memory, 0x0010, &[0x11, 0x22]);
+
+    // Reads lag one behind through the buffer
+    write(&mut memory, 0x0010, &[]);
+    assert_eq!(memory.get(0x2007), 0x00);
+    assert_eq!(memory.get(0x2007), 0x11);
+    assert_eq!(memory.get(0x2007), 0x22);
+
+    // Palette reads come straight back and buffer the nametable underneath
+    write(&mut memory, 0x3F01, &[]);
+    assert_eq!(memory.get(0x2007) & 0x3F, 0x21);
+    write(&mut memory, 0x3F00, &[]);
+    assert_eq!(memory.get(0x2007) & 0x3F, 0x0F);
+    write(&mut memory, 0x0010, &[]);
+    assert_eq!(memory.get(0x2007), 0xAA);
+
+    // $3F14 is a mirror of $3F04
+    assert_eq!(memory.ppu_get(0x3F04), 0x30);
+
+    // The address wraps from $3FFF to $0000
+    write(&mut memory, 0x3FFF, &[0x01, 0x33]);
+    assert_eq!(memory.ppu_get(0x0000), 0x33);
+}
+
+#[test]
+fn expansion_area() {
+    let mut memory = Memory::with_prg(vec![0; 0x4000]);
+
+    // Unmapped on a plain cartridge: open bus on reads, writes go nowhere
+    memory.set(0x5000, 0x12);
+    assert_eq!(memory.get(0x5000), 0x50);
+    assert_eq!(memory.get(0x4020), 0x40);
+}
+
+#[test]
+fn prg_ram_save() {
+    let new_memory = || Memory::with_prg(vec![0; 0x4000]).with_prg_ram(0x2000);
+    let path = std::env::temp_dir().join(format!("nemulator-{}.sav", std::process::id()));
+
+    // A missing save is a fresh cartridge
+    let mut memory = new_memory();
+    memory.load_prg_ram(&path).unwrap();
+    assert_eq!(memory.get(0x6000), 0x00);
+
+    memory.set(0x6000, 0xAB);
+    memory.set(0x7FFF, 0xCD);
+    memory.save_prg_ram(&path).unwrap();
+
+    let mut memory = new_memory();
+    memory.load_prg_ram(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(memory.get(0x6000), 0xAB);
+    assert_eq!(memory.get(0x7FFF), 0xCD);
+
+    // Nothing changed since the load, so nothing is written
+    memory.save_prg_ram(&path).unwrap();
+    assert!(!path.exists());
+}
+
+#[test]
+fn chr_ram() {
+    let write_read = |memory: &mut Memory| {
+        memory.set(0x2006, 0x12);
+        memory.set(0x2006, 0x34);
+        memory.set(0x2007, 0x5A);
+        memory.set(0x2006, 0x12);
+        memory.set(0x2006, 0x34);
+        // The first read only fills the buffer
+        memory.get(0x2007);
+        memory.get(0x2007)
+    };
+
+    assert_eq!(write_read(&mut Memory::with_prg(vec![0; 0x4000])), 0x5A);
+    // CHR-ROM ignores the write
+    assert_eq!(
+        write_read(&mut Memory::with_prg(vec![0; 0x4000]).with_chr_rom(vec![0; 0x2000])),
+        0x00
+    );
+    // Neither, the pattern bus reads 0
+    assert_eq!(
+        write_read(&mut Memory::with_prg(vec![0; 0x4000]).with_chr_ram(0)),
+        0x00
+    );
+}
+
+#[test]
+fn no_prg_rom() {
+    let mut memory = Memory::with_prg(vec![]);
+
+    // Nothing to read the vectors from, the bus floats instead of dividing by zero
+    assert_eq!(memory.get(0xFFFC), 0xFF);
+    assert_eq!(memory.get(0x8000), 0x80);
+}
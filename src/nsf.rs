@@ -0,0 +1,105 @@
+#[cfg(test)]
+mod tests;
+
+use crate::cpu::CPU;
+use crate::memory::Memory;
+use crate::rom_reader::nsf::NsfInfo;
+
+// Player stub mapped at $5000: JMP $5000, the CPU idles there between INIT and PLAY calls
+pub const IDLE_ADDRESS: u16 = 0x5000;
+const IDLE_LOOP: [u8; 3] = [0x4C, 0x00, 0x50];
+
+const NTSC_CPU_CLOCK: f64 = 1_789_773.0;
+const PAL_CPU_CLOCK: f64 = 1_662_607.0;
+
+pub fn stub_read(address: u16) -> u8 {
+    address
+        .checked_sub(IDLE_ADDRESS)
+        .and_then(|offset| IDLE_LOOP.get(offset as usize))
+        .copied()
+        .unwrap_or(0)
+}
+
+pub struct NsfPlayer {
+    pub info: NsfInfo,
+    song: u8,
+    clock: f64,
+    // CPU cycles between PLAY calls
+    play_period: u64,
+    play_counter: u64,
+    elapsed_cycles: u64,
+    init_pending: bool,
+}
+
+impl NsfPlayer {
+    pub fn new(info: NsfInfo) -> NsfPlayer {
+        let (speed, clock) = if info.pal {
+            (info.pal_speed, PAL_CPU_CLOCK)
+        } else {
+            (info.ntsc_speed, NTSC_CPU_CLOCK)
+        };
+
+        NsfPlayer {
+            song: info.starting_song.min(info.songs - 1),
+            clock,
+            play_period: ((speed as f64 * clock / 1_000_000.0) as u64).max(1),
+            play_counter: 0,
+            elapsed_cycles: 0,
+            init_pending: true,
+            info,
+        }
+    }
+
+    pub fn song(&self) -> u8 {
+        self.song
+    }
+
+    pub fn elapsed_seconds(&self) -> f64 {
+        self.elapsed_cycles as f64 / self.clock
+    }
+
+    pub fn select_song(&mut self, song: u8) {
+        self.song = song % self.info.songs;
+        self.init_pending = true;
+    }
+
+    pub fn next_song(&mut self) {
+        self.select_song(self.song.wrapping_add(1));
+    }
+
+    pub fn previous_song(&mut self) {
+        self.select_song(self.song.checked_sub(1).unwrap_or(self.info.songs - 1));
+    }
+
+    // Runs once per CPU cycle, before the CPU itself
+    pub fn cycle(&mut self, cpu: &mut CPU, memory: &mut Memory, emulator_cycle: u64) {
+        self.elapsed_cycles += 1;
+        self.play_counter += 1;
+
+        if !cpu.at_instruction_boundary(emulator_cycle) {
+            return;
+        }
+
+        if self.init_pending {
+            memory.reset_nsf(self.info.bankswitch.unwrap_or([0, 1, 2, 3, 4, 5, 6, 7]));
+            cpu.reset_stack();
+            cpu.call(
+                memory,
+                self.info.init_address,
+                IDLE_ADDRESS,
+                self.song,
+                self.info.pal as u8,
+            );
+            self.init_pending = false;
+            self.play_counter = 0;
+            self.elapsed_cycles = 0;
+            return;
+        }
+
+        // A PLAY routine that overruns its period just delays the next call
+        if self.play_counter >= self.play_period && cpu.program_counter() == IDLE_ADDRESS {
+            self.play_counter = 0;
+            cpu.call(memory, self.info.play_address, IDLE_ADDRESS, 0, 0);
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use crate::cpu::CPU;
+use crate::memory::Memory;
+use crate::nsf::{IDLE_ADDRESS, NsfPlayer};
+use crate::rom_reader::nsf::NsfInfo;
+
+#[test]
+fn init_and_play() {
+    // INIT: STA $00, RTS / PLAY: INC $01, RTS
+    let mut prg_rom = vec![0; 0x8000];
+    prg_rom[0..6].copy_from_slice(&[0x85, 0x00, 0x60, 0xE6, 0x01, 0x60]);
+    let mut memory = Memory::with_prg(prg_rom).with_prg_ram(0x2000);
+    let mut cpu = CPU::new(&mut memory, None);
+    let mut player = NsfPlayer::new(NsfInfo {
+        init_address: 0x8000,
+        play_address: 0x8003,
+        songs: 4,
+        starting_song: 2,
+        ntsc_speed: 1000,
+        ..Default::default()
+    });
+
+    // 1 ms at the NTSC clock is 1789 cycles per PLAY call
+    let mut emulator_cycle = 7;
+    for _ in 0..1789 * 3 + 100 {
+        player.cycle(&mut cpu, &mut memory, emulator_cycle);
+        cpu.cycle(&mut memory, emulator_cycle).unwrap();
+        emulator_cycle += 1;
+    }
+
+    assert_eq!(memory.get(0x00), 2);
+    assert_eq!(memory.get(0x01), 3);
+    assert_eq!(cpu.program_counter(), IDLE_ADDRESS);
+
+    player.next_song();
+    for _ in 0..100 {
+        player.cycle(&mut cpu, &mut memory, emulator_cycle);
+        cpu.cycle(&mut memory, emulator_cycle).unwrap();
+        emulator_cycle += 1;
+    }
+    assert_eq!(memory.get(0x00), 3);
+    assert_eq!(memory.get(0x01), 0);
+}
@@ -0,0 +1,253 @@
+use std::f64::consts::PI;
+use std::path::Path;
+
+#[cfg(test)]
+mod tests;
+
+// 64 colors times the 8 combinations of the PPUMASK emphasis bits
+pub const PALETTE_SIZE: usize = 512;
+
+// The common 2C02 palette, indexed by the 6-bit color from palette RAM
+const NTSC_PALETTE: [[u8; 3]; 64] = [
+    [84, 84, 84],
+    [0, 30, 116],
+    [8, 16, 144],
+    [48, 0, 136],
+    [68, 0, 100],
+    [92, 0, 48],
+    [84, 4, 0],
+    [60, 24, 0],
+    [32, 42, 0],
+    [8, 58, 0],
+    [0, 64, 0],
+    [0, 60, 0],
+    [0, 50, 60],
+    [0, 0, 0],
+    [0, 0, 0],
+    [0, 0, 0],
+    [152, 150, 152],
+    [8, 76, 196],
+    [48, 50, 236],
+    [92, 30, 228],
+    [136, 20, 176],
+    [160, 20, 100],
+    [152, 34, 32],
+    [120, 60, 0],
+    [84, 90, 0],
+    [40, 114, 0],
+    [8, 124, 0],
+    [0, 118, 40],
+    [0, 102, 120],
+    [0, 0, 0],
+    [0, 0, 0],
+    [0, 0, 0],
+    [236, 238, 236],
+    [76, 154, 236],
+    [120, 124, 236],
+    [176, 98, 236],
+    [228, 84, 236],
+    [236, 88, 180],
+    [236, 106, 100],
+    [212, 136, 32],
+    [160, 170, 0],
+    [116, 196, 0],
+    [76, 208, 32],
+    [56, 204, 108],
+    [56, 180, 204],
+    [60, 60, 60],
+    [0, 0, 0],
+    [0, 0, 0],
+    [236, 238, 236],
+    [168, 204, 236],
+    [188, 188, 236],
+    [212, 178, 236],
+    [236, 174, 236],
+    [236, 174, 212],
+    [236, 180, 176],
+    [228, 196, 144],
+    [204, 210, 120],
+    [180, 222, 120],
+    [168, 226, 144],
+    [152, 226, 180],
+    [160, 214, 228],
+    [160, 162, 160],
+    [0, 0, 0],
+    [0, 0, 0],
+];
+
+// How much an emphasis bit darkens the two other channels in RGB terms
+const EMPHASIS_ATTENUATION: f64 = 0.816328;
+
+// Knobs for Palette::generate, the defaults give the stock NTSC look
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PaletteSettings {
+    // Degrees added to every color's phase
+    pub hue: f64,
+    pub saturation: f64,
+    pub contrast: f64,
+    // Added to luma, -1.0 to 1.0
+    pub brightness: f64,
+}
+
+impl Default for PaletteSettings {
+    fn default() -> PaletteSettings {
+        PaletteSettings {
+            hue: 0.0,
+            saturation: 1.0,
+            contrast: 1.0,
+            brightness: 0.0,
+        }
+    }
+}
+
+pub struct Palette {
+    // Indexed by emphasis << 6 | color
+    colors: Vec<[u8; 3]>,
+}
+
+impl Palette {
+    pub fn ntsc() -> Palette {
+        Palette::with_emphasis(&NTSC_PALETTE)
+    }
+
+    // An approximation, not measured 2C07 colors: the NTSC generator with its hues rotated
+    // about half a step, which is roughly where the 2C07's sit. Load a .pal file for accuracy
+    pub fn pal() -> Palette {
+        Palette::generate(&PaletteSettings {
+            hue: -15.0,
+            ..PaletteSettings::default()
+        })
+    }
+
+    // 64-color palettes get their emphasis variants by dimming the non-emphasized channels
+    fn with_emphasis(colors: &[[u8; 3]]) -> Palette {
+        let colors = (0..PALETTE_SIZE)
+            .map(|index| {
+                let emphasis = index >> 6;
+                let mut rgb = colors[index & 0x3F];
+                for (channel, value) in rgb.iter_mut().enumerate() {
+                    // Each set bit other than this channel's own dims it
+                    let dimming = (emphasis & !(1 << channel)).count_ones();
+                    *value = (*value as f64 * EMPHASIS_ATTENUATION.powi(dimming as i32)) as u8;
+                }
+                rgb
+            })
+            .collect();
+
+        Palette { colors }
+    }
+
+    // .pal files are raw RGB triplets, 64 colors or all 512 with emphasis
+    pub fn from_bytes(bytes: &[u8]) -> Option<Palette> {
+        let colors: Vec<[u8; 3]> = bytes
+            .chunks_exact(3)
+            .map(|rgb| [rgb[0], rgb[1], rgb[2]])
+            .collect();
+        match bytes.len() {
+            192 => Some(Palette::with_emphasis(&colors)),
+            1536 => Some(Palette { colors }),
+            _ => None,
+        }
+    }
+
+    pub fn load(path: &Path) -> std::io::Result<Palette> {
+        let bytes = std::fs::read(path)?;
+        Palette::from_bytes(&bytes).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "{} is {} bytes, a palette is 192 or 1536",
+                    path.display(),
+                    bytes.len()
+                ),
+            )
+        })
+    }
+
+    // Simulates the composite signal and decodes it like a TV would,
+    // after Bisqwit's generator, see https://www.nesdev.org/wiki/NTSC_video
+    pub fn generate(settings: &PaletteSettings) -> Palette {
+        let colors = (0..PALETTE_SIZE as u16)
+            .map(|index| generate_color(index, settings))
+            .collect();
+
+        Palette { colors }
+    }
+
+    // 9-bit index: emphasis bits from PPUMASK above the 6-bit palette RAM value
+    pub fn rgb(&self, index: u16) -> [u8; 3] {
+        self.colors[index as usize % PALETTE_SIZE]
+    }
+}
+
+// Signal levels in volts, low and high halves of the square wave for each luma level
+const SIGNAL_LOW: [f64; 4] = [0.228, 0.312, 0.552, 0.880];
+const SIGNAL_HIGH: [f64; 4] = [0.616, 0.840, 1.100, 1.100];
+const SIGNAL_BLACK: f64 = 0.312;
+const SIGNAL_WHITE: f64 = 1.100;
+const SIGNAL_ATTENUATION: f64 = 0.746;
+// Phase shift that lines the decoder up with the PPU's color $1, in 30 degree steps
+const PHASE_OFFSET: f64 = 3.9;
+
+// Voltage for one of the 12 phases of a color's cycle
+fn ntsc_signal(index: u16, phase: u16) -> f64 {
+    let color = index & 0x0F;
+    let mut level = ((index >> 4) & 0b11) as usize;
+    let emphasis = index >> 6;
+    // $xE and $xF are forced black
+    if color > 13 {
+        level = 1;
+    }
+
+    let mut low = SIGNAL_LOW[level];
+    let mut high = SIGNAL_HIGH[level];
+    // Color 0 is a flat high level, 13-15 a flat low one
+    if color == 0 {
+        low = high;
+    }
+    if color > 12 {
+        high = low;
+    }
+
+    let in_color_phase = |color: u16| (color + phase) % 12 < 6;
+    let signal = if in_color_phase(color) { high } else { low };
+    if (emphasis & 0b001 != 0 && in_color_phase(0))
+        || (emphasis & 0b010 != 0 && in_color_phase(4))
+        || (emphasis & 0b100 != 0 && in_color_phase(8))
+    {
+        signal * SIGNAL_ATTENUATION
+    } else {
+        signal
+    }
+}
+
+fn generate_color(index: u16, settings: &PaletteSettings) -> [u8; 3] {
+    let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+    for phase in 0..12 {
+        let level =
+            (ntsc_signal(index, phase) - SIGNAL_BLACK) / (SIGNAL_WHITE - SIGNAL_BLACK) / 12.0;
+        let angle = PI / 6.0 * (phase as f64 + PHASE_OFFSET + settings.hue / 30.0);
+        y += level;
+        i += level * angle.cos();
+        q += level * angle.sin();
+    }
+
+    y = y * settings.contrast + settings.brightness;
+    i *= settings.saturation * settings.contrast;
+    q *= settings.saturation * settings.contrast;
+
+    // YIQ to RGB, then from the TV's 2.2 gamma to the monitor's 1.8
+    let gamma = |value: f64| {
+        let corrected = if value <= 0.0 {
+            0.0
+        } else {
+            value.powf(2.2 / 1.8)
+        };
+        (corrected * 255.0).round().clamp(0.0, 255.0) as u8
+    };
+    [
+        gamma(y + 0.946882 * i + 0.623557 * q),
+        gamma(y - 0.274788 * i - 0.635691 * q),
+        gamma(y - 1.108545 * i + 1.709007 * q),
+    ]
+}
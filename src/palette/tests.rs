@@ -0,0 +1,45 @@
+use crate::palette::{PALETTE_SIZE, Palette, PaletteSettings};
+
+#[test]
+fn pal_files() {
+    assert!(Palette::from_bytes(&[0; 191]).is_none());
+
+    let mut bytes = vec![0; 192];
+    bytes[0x16 * 3..0x16 * 3 + 3].copy_from_slice(&[200, 100, 50]);
+    let palette = Palette::from_bytes(&bytes).unwrap();
+    assert_eq!(palette.rgb(0x16), [200, 100, 50]);
+    // Red emphasis dims green and blue, the other two bits also dim red
+    assert_eq!(palette.rgb(0b001 << 6 | 0x16), [200, 81, 40]);
+    assert_eq!(palette.rgb(0b111 << 6 | 0x16)[0], 133);
+
+    let bytes: Vec<u8> = (0..PALETTE_SIZE * 3).map(|i| i as u8).collect();
+    let palette = Palette::from_bytes(&bytes).unwrap();
+    assert_eq!(palette.rgb(0x1FF), [0xFD, 0xFE, 0xFF]);
+}
+
+#[test]
+fn generated() {
+    let palette = Palette::generate(&PaletteSettings::default());
+    let [r, g, b] = palette.rgb(0x16);
+    assert!(r > g && r > b, "$16 should be red");
+    let [r, g, b] = palette.rgb(0x1A);
+    assert!(g > r && g > b, "$1A should be green");
+    let [r, g, b] = palette.rgb(0x12);
+    assert!(b > r && b > g, "$12 should be blue");
+    assert_eq!(palette.rgb(0x30), [255, 255, 255]);
+    assert_eq!(palette.rgb(0x0F), [0, 0, 0]);
+
+    // A half turn of hue lands on the opposite side of the color wheel
+    let shifted = Palette::generate(&PaletteSettings {
+        hue: 180.0,
+        ..Default::default()
+    });
+    assert_eq!(shifted.rgb(0x16), palette.rgb(0x1C));
+
+    let grey = Palette::generate(&PaletteSettings {
+        saturation: 0.0,
+        ..Default::default()
+    });
+    let [r, g, b] = grey.rgb(0x16);
+    assert!(r == g && g == b);
+}
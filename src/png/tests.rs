@@ -0,0 +1,27 @@
+use crate::png::encode_rgb;
+
+#[test]
+fn stored_png() {
+    let rgb: Vec<u8> = (0..2 * 2 * 3).collect();
+    let png = encode_rgb(2, 2, &rgb);
+
+    assert_eq!(
+        png[..8],
+        [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n']
+    );
+    assert_eq!(png[12..16], *b"IHDR");
+    assert_eq!(png[16..29], [0, 0, 0, 2, 0, 0, 0, 2, 8, 2, 0, 0, 0]);
+
+    // zlib header, one final stored block of 2 filtered rows, Adler-32
+    let idat = &png[37..];
+    assert_eq!(idat[..4], *b"IDAT");
+    assert_eq!(idat[4..11], [0x78, 0x01, 0x01, 14, 0, !14, 0xFF]);
+    assert_eq!(idat[11..25], [0, 0, 1, 2, 3, 4, 5, 0, 6, 7, 8, 9, 10, 11]);
+    assert_eq!(idat[25..29], [0x01, 0x3B, 0x00, 0x43]);
+
+    // IEND's CRC is the same in every PNG
+    assert_eq!(
+        png[png.len() - 12..],
+        [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]
+    );
+}
@@ -1,55 +1,201 @@
 use crate::memory::Memory;
-use raylib::prelude::*;
+use crate::region::Region;
+
+#[cfg(test)]
+mod tests;
+
+pub const SCREEN_WIDTH: usize = 256;
+pub const SCREEN_HEIGHT: usize = 240;
+
+// Scanlines 0-239 are visible, the region decides where vblank starts and how many
+// lines follow before the pre-render line closes the frame
+const DOTS_PER_SCANLINE: u16 = 341;
+// Sprites past the eighth on a scanline are dropped
+pub const SPRITES_PER_SCANLINE: usize = 8;
+// Open bus bits fade to 0 when nothing refreshes them for about 600 ms
+const OPEN_BUS_DECAY_FRAMES: u8 = 36;
 
 pub struct PPURegisters {
+    region: Region,
     ppuctrl: u8,
     ppumask: u8,
     ppustatus: u8,
     oamaddr: u8,
-    ppuscroll: u16,
-    ppuaddr: u16,
     ppudata_buffer: u8,
+    // Last value driven on the PPU data bus, unused PPUSTATUS bits read back from it
+    open_bus: u8,
+    // Frames left before each bit of open_bus decays
+    open_bus_decay: [u8; 8],
 
+    // Loopy's internal registers, see https://www.nesdev.org/wiki/PPU_scrolling
+    // v: current VRAM address, t: temporary VRAM address (top left of the screen),
+    // x: fine X scroll, w: first/second write toggle shared by $2005 and $2006
+    v: u16,
+    t: u16,
+    x: u8,
     w: bool,
-    draw_nametable: u8,
-    draw_attr_table: u8,
-    draw_pattern_table_lo: u8,
-    draw_pattern_table_hi: u8,
 
+    scanline: u16,
+    dot: u16,
+    // Set when the last dot of the pre-render line is done, taken by the scheduler
+    frame_complete: bool,
+    odd_frame: bool,
+    // A PPUSTATUS read right before vblank starts keeps it from being set this frame
+    suppress_vblank: bool,
+
+    // The next tile, fetched over 8 dots
     nametable_latch: u8,
     attr_table_latch: u8,
     pattern_table_lo_latch: u8,
     pattern_table_hi_latch: u8,
+
+    // High byte is the tile being drawn, low byte the next one
+    pattern_shift_lo: u16,
+    pattern_shift_hi: u16,
+    attr_shift_lo: u16,
+    attr_shift_hi: u16,
+
+    // 64 sprites of 4 bytes: Y, tile, attributes, X
+    oam: [u8; 256],
+    // Sprites found on the current scanline during evaluation, rendered on the next one
+    secondary_oam: [u8; 32],
+    sprite_count: usize,
+    sprite_zero_in_secondary_oam: bool,
+
+    // Fetched data for the sprites of the scanline being drawn
+    sprite_count_line: usize,
+    sprite_zero_on_line: bool,
+    sprite_pattern_lo: [u8; SPRITES_PER_SCANLINE],
+    sprite_pattern_hi: [u8; SPRITES_PER_SCANLINE],
+    sprite_attributes: [u8; SPRITES_PER_SCANLINE],
+    sprite_x: [u8; SPRITES_PER_SCANLINE],
+
+    // One 9-bit palette index per pixel, emphasis << 6 | color, turned into RGB by the frontend
+    framebuffer: Vec<u16>,
+}
+
+impl Default for PPURegisters {
+    fn default() -> PPURegisters {
+        PPURegisters::new()
+    }
 }
 
 impl PPURegisters {
     pub fn new() -> PPURegisters {
         PPURegisters {
+            region: Region::Ntsc,
             ppuctrl: 0,
             ppumask: 0,
             ppustatus: 0b10100000,
             oamaddr: 0,
-            ppuscroll: 0,
-            ppuaddr: 0,
             ppudata_buffer: 0,
+            open_bus: 0,
+            open_bus_decay: [0; 8],
 
+            v: 0,
+            t: 0,
+            x: 0,
             w: false,
-            draw_nametable: 0,
-            draw_attr_table: 0,
-            draw_pattern_table_lo: 0,
-            draw_pattern_table_hi: 0,
+
+            scanline: 0,
+            dot: 0,
+            frame_complete: false,
+            odd_frame: false,
+            suppress_vblank: false,
 
             nametable_latch: 0,
             attr_table_latch: 0,
             pattern_table_lo_latch: 0,
             pattern_table_hi_latch: 0,
+
+            pattern_shift_lo: 0,
+            pattern_shift_hi: 0,
+            attr_shift_lo: 0,
+            attr_shift_hi: 0,
+
+            oam: [0; 256],
+            secondary_oam: [0xFF; 32],
+            sprite_count: 0,
+            sprite_zero_in_secondary_oam: false,
+
+            sprite_count_line: 0,
+            sprite_zero_on_line: false,
+            sprite_pattern_lo: [0; SPRITES_PER_SCANLINE],
+            sprite_pattern_hi: [0; SPRITES_PER_SCANLINE],
+            sprite_attributes: [0; SPRITES_PER_SCANLINE],
+            sprite_x: [0; SPRITES_PER_SCANLINE],
+
+            framebuffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
         }
     }
 
+    fn get_ppuctrl_nmi_enable(&self) -> bool {
+        self.ppuctrl & 0b1000_0000 != 0
+    }
+
     fn get_ppuctrl_increment_mode(&self) -> bool {
         self.ppuctrl & 0b0000_0100 != 0
     }
 
+    pub fn get_ppuctrl_background_table(&self) -> u16 {
+        if self.ppuctrl & 0b0001_0000 != 0 {
+            0x1000
+        } else {
+            0x0000
+        }
+    }
+
+    // Only used by 8x8 sprites, 8x16 sprites pick their table with bit 0 of the tile index
+    fn get_ppuctrl_sprite_table(&self) -> u16 {
+        if self.ppuctrl & 0b0000_1000 != 0 {
+            0x1000
+        } else {
+            0x0000
+        }
+    }
+
+    pub fn get_ppuctrl_sprite_height(&self) -> u16 {
+        if self.ppuctrl & 0b0010_0000 != 0 {
+            16
+        } else {
+            8
+        }
+    }
+
+    fn get_ppumask_greyscale(&self) -> bool {
+        self.ppumask & 0b0000_0001 != 0
+    }
+
+    fn get_ppumask_show_background_left(&self) -> bool {
+        self.ppumask & 0b0000_0010 != 0
+    }
+
+    fn get_ppumask_show_sprites_left(&self) -> bool {
+        self.ppumask & 0b0000_0100 != 0
+    }
+
+    // Red, green and blue emphasis as the top 3 bits of a 9-bit palette index
+    fn get_ppumask_emphasis(&self) -> u8 {
+        let emphasis = self.ppumask >> 5;
+        if self.region.swaps_emphasis() {
+            (emphasis & 0b100) | ((emphasis & 0b001) << 1) | ((emphasis & 0b010) >> 1)
+        } else {
+            emphasis
+        }
+    }
+
+    fn get_ppumask_show_sprites(&self) -> bool {
+        self.ppumask & 0b0001_0000 != 0
+    }
+
+    fn get_ppumask_show_background(&self) -> bool {
+        self.ppumask & 0b0000_1000 != 0
+    }
+
+    fn rendering_enabled(&self) -> bool {
+        self.ppumask & 0b0001_1000 != 0
+    }
+
     fn set_ppustatus_vblank(&mut self, value: bool) {
         if value {
             self.ppustatus |= 0b1000_0000;
@@ -58,112 +204,593 @@ impl PPURegisters {
         }
     }
 
-    pub fn set(&mut self, address: u16, value: u8, vram: &mut Vec<u8>, palettes: &mut Vec<u8>) {
+    fn get_ppustatus_vblank(&self) -> bool {
+        self.ppustatus & 0b1000_0000 != 0
+    }
+
+    fn set_ppustatus_sprite_zero_hit(&mut self, value: bool) {
+        if value {
+            self.ppustatus |= 0b0100_0000;
+        } else {
+            self.ppustatus &= 0b1011_1111;
+        }
+    }
+
+    fn set_ppustatus_sprite_overflow(&mut self, value: bool) {
+        if value {
+            self.ppustatus |= 0b0010_0000;
+        } else {
+            self.ppustatus &= 0b1101_1111;
+        }
+    }
+
+    pub fn framebuffer(&self) -> &[u16] {
+        &self.framebuffer
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    fn vblank_scanline(&self) -> u16 {
+        self.region.vblank_scanline()
+    }
+
+    fn pre_render_scanline(&self) -> u16 {
+        self.region.scanlines() - 1
+    }
+
+    // Top left of the next frame in the 512x480 space of the four nametables, from t and fine X
+    pub fn scroll_origin(&self) -> (u16, u16) {
+        let x = ((self.t >> 10) & 1) * 256 + (self.t & 0x1F) * 8 + self.x as u16;
+        let y = ((self.t >> 11) & 1) * 240 + ((self.t >> 5) & 0x1F) * 8 + (self.t >> 12);
+        (x, y)
+    }
+
+    pub fn oam(&self) -> &[u8; 256] {
+        &self.oam
+    }
+
+    pub fn take_frame_complete(&mut self) -> bool {
+        std::mem::take(&mut self.frame_complete)
+    }
+
+    // Scanline and dot the next ppu_cycle renders
+    pub fn position(&self) -> (u16, u16) {
+        (self.scanline, self.dot)
+    }
+
+    // The reset line clears the control registers, the scroll, the read buffer and the
+    // odd frame flag, v, OAM and the dot counters carry on
+    pub fn reset(&mut self) {
+        self.ppuctrl = 0;
+        self.ppumask = 0;
+        self.ppudata_buffer = 0;
+        self.t = 0;
+        self.x = 0;
+        self.w = false;
+        self.odd_frame = false;
+    }
+
+    // Address the next $2007 access goes to
+    pub fn data_address(&self) -> u16 {
+        self.v & 0x3FFF
+    }
+
+    // The NMI line stays low while either flag is clear, so enabling NMI
+    // in the middle of vblank raises it again and the CPU sees a new edge
+    pub fn nmi_output(&self) -> bool {
+        self.get_ppustatus_vblank() && self.get_ppuctrl_nmi_enable()
+    }
+
+    fn start_vblank(&mut self) {
+        if !self.suppress_vblank {
+            self.set_ppustatus_vblank(true);
+        }
+        self.suppress_vblank = false;
+    }
+
+    fn end_vblank(&mut self) {
+        self.set_ppustatus_vblank(false);
+        self.set_ppustatus_sprite_zero_hit(false);
+        self.set_ppustatus_sprite_overflow(false);
+    }
+
+    // Only the bits in mask are driven, the rest keep whatever was on the bus
+    fn drive_open_bus(&mut self, value: u8, mask: u8) {
+        self.open_bus = (self.open_bus & !mask) | (value & mask);
+        for bit in 0..8 {
+            if mask & (1 << bit) != 0 {
+                self.open_bus_decay[bit] = OPEN_BUS_DECAY_FRAMES;
+            }
+        }
+    }
+
+    fn decay_open_bus(&mut self) {
+        for bit in 0..8 {
+            if self.open_bus_decay[bit] > 0 {
+                self.open_bus_decay[bit] -= 1;
+                if self.open_bus_decay[bit] == 0 {
+                    self.open_bus &= !(1 << bit);
+                }
+            }
+        }
+    }
+
+    // While rendering, v belongs to the scroll counters and a $2007 access bumps
+    // coarse X and Y instead of adding 1 or 32
+    fn increment_data_address(&mut self) {
+        if self.rendering_enabled()
+            && (self.scanline < 240 || self.scanline == self.pre_render_scanline())
+        {
+            self.increment_coarse_x();
+            self.increment_y();
+        } else if self.get_ppuctrl_increment_mode() {
+            self.v = self.v.wrapping_add(32) & 0x7FFF;
+        } else {
+            self.v = self.v.wrapping_add(1) & 0x7FFF;
+        }
+    }
+
+    // PPUDATA reads return the buffer and refill it, except for palette reads which come
+    // straight back and leave the nametable byte underneath the palette in the buffer
+    pub fn read_data(&mut self, value: u8, underneath: u8) -> u8 {
+        let ret_value = if self.data_address() >= 0x3F00 {
+            self.ppudata_buffer = underneath;
+            // Palette entries are 6 bits, the top 2 are open bus
+            self.drive_open_bus(value, 0b0011_1111);
+            self.open_bus
+        } else {
+            let buffered = self.ppudata_buffer;
+            self.ppudata_buffer = value;
+            self.drive_open_bus(buffered, 0xFF);
+            buffered
+        };
+        self.increment_data_address();
+
+        ret_value
+    }
+
+    pub fn set(&mut self, address: u16, value: u8) {
+        self.drive_open_bus(value, 0xFF);
         match address & 0x0007 {
-            0 => self.ppuctrl = value,
+            0 => {
+                self.ppuctrl = value;
+                // Nametable select lives in t
+                self.t = (self.t & !0x0C00) | ((value as u16 & 0b11) << 10);
+            }
             1 => self.ppumask = value,
             2 => {}
-            6 => {
+            3 => self.oamaddr = value,
+            4 => {
+                // Bits 2-4 of the attribute byte don't exist in OAM
+                let value = if self.oamaddr & 0b11 == 2 {
+                    value & 0b1110_0011
+                } else {
+                    value
+                };
+                self.oam[self.oamaddr as usize] = value;
+                self.oamaddr = self.oamaddr.wrapping_add(1);
+            }
+            5 => {
                 if !self.w {
-                    self.ppuaddr &= 0x0F;
-                    self.ppuaddr |= (value as u16) << 8;
-                    self.w = true;
+                    self.t = (self.t & !0x001F) | (value as u16 >> 3);
+                    self.x = value & 0b111;
                 } else {
-                    self.ppuaddr &= 0xF0;
-                    self.ppuaddr |= value as u16;
-                    self.w = false;
+                    self.t = (self.t & !0x73E0)
+                        | ((value as u16 & 0b111) << 12)
+                        | ((value as u16 & 0b1111_1000) << 2);
                 }
+                self.w = !self.w;
             }
-            7 => {
-                match self.ppuaddr {
-                    0x2000..=0x2FFF => vram[((self.ppuaddr - 0x2000) % 2048) as usize] = value,
-                    0x3F00..=0x3FFF => {
-                        palettes[((self.ppuaddr - 0x3F00) & 0b0001_1111) as usize] = value
-                    }
-                    _ => {}
-                }
-
-                if self.get_ppuctrl_increment_mode() {
-                    self.ppuaddr += 32;
+            6 => {
+                if !self.w {
+                    // Bit 14 of t is cleared, the upper bits of the address are lost
+                    self.t = (self.t & 0x00FF) | ((value as u16 & 0b0011_1111) << 8);
                 } else {
-                    self.ppuaddr += 1;
+                    self.t = (self.t & 0xFF00) | value as u16;
+                    self.v = self.t;
                 }
+                self.w = !self.w;
             }
-
-            _ => todo!(),
+            // Memory does the actual write at data_address
+            7 => self.increment_data_address(),
+            _ => unreachable!(),
         }
     }
 
     pub fn get(&mut self, address: u16) -> u8 {
         match address & 0x0007 {
             2 => {
-                let ret_value = self.ppustatus;
+                // One dot early the flag reads clear and never gets set. A read on the dots
+                // right after it is set clears it before the CPU samples the NMI line, which
+                // suppresses that NMI too
+                if self.scanline == self.vblank_scanline() && self.dot == 1 {
+                    self.suppress_vblank = true;
+                }
+                self.drive_open_bus(self.ppustatus, 0b1110_0000);
                 self.set_ppustatus_vblank(false);
-                // TODO: Add open bus behaviour, see https://www.nesdev.org/wiki/PPU_registers#PPUSTATUS
+                self.w = false;
 
-                ret_value
+                self.open_bus
+            }
+            // Reads don't increment OAMADDR
+            4 => {
+                self.drive_open_bus(self.oam[self.oamaddr as usize], 0xFF);
+                self.open_bus
             }
 
-            _ => 0,
+            // Write-only registers read back the bus, $2007 goes through read_data
+            _ => self.open_bus,
+        }
+    }
+
+    fn increment_coarse_x(&mut self) {
+        if self.v & 0x001F == 31 {
+            // Wrap into the horizontally adjacent nametable
+            self.v &= !0x001F;
+            self.v ^= 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+            return;
+        }
+
+        self.v &= !0x7000;
+        let mut coarse_y = (self.v & 0x03E0) >> 5;
+        if coarse_y == 29 {
+            // Row 29 is the last of the nametable, wrap into the vertically adjacent one
+            coarse_y = 0;
+            self.v ^= 0x0800;
+        } else if coarse_y == 31 {
+            // Rows 30 and 31 are attribute memory, wrap without switching nametables
+            coarse_y = 0;
+        } else {
+            coarse_y += 1;
+        }
+        self.v = (self.v & !0x03E0) | (coarse_y << 5);
+    }
+
+    fn transfer_x(&mut self) {
+        self.v = (self.v & !0x041F) | (self.t & 0x041F);
+    }
+
+    fn transfer_y(&mut self) {
+        self.v = (self.v & !0x7BE0) | (self.t & 0x7BE0);
+    }
+
+    fn nametable_address(&self) -> u16 {
+        0x2000 | (self.v & 0x0FFF)
+    }
+
+    fn attr_table_address(&self) -> u16 {
+        0x23C0 | (self.v & 0x0C00) | ((self.v >> 4) & 0x38) | ((self.v >> 2) & 0x07)
+    }
+
+    fn pattern_table_address(&self) -> u16 {
+        self.get_ppuctrl_background_table()
+            + self.nametable_latch as u16 * 16
+            + ((self.v >> 12) & 0b111)
+    }
+
+    // Each attribute byte covers 4x4 tiles, pick the 2x2 quadrant v points into
+    fn set_attr_table_latch(&mut self, attr_byte: u8) {
+        let shift = ((self.v >> 4) & 0b100) | (self.v & 0b10);
+        self.attr_table_latch = (attr_byte >> shift) & 0b11;
+    }
+
+    fn load_background_shifters(&mut self) {
+        self.pattern_shift_lo =
+            (self.pattern_shift_lo & 0xFF00) | self.pattern_table_lo_latch as u16;
+        self.pattern_shift_hi =
+            (self.pattern_shift_hi & 0xFF00) | self.pattern_table_hi_latch as u16;
+        // The palette is the same for all 8 pixels, widen it to match the pattern shifters
+        self.attr_shift_lo =
+            (self.attr_shift_lo & 0xFF00) | ((self.attr_table_latch & 0b01) as u16 * 0xFF);
+        self.attr_shift_hi =
+            (self.attr_shift_hi & 0xFF00) | ((self.attr_table_latch >> 1) as u16 * 0xFF);
+    }
+
+    fn shift_background(&mut self) {
+        if self.get_ppumask_show_background() {
+            self.pattern_shift_lo <<= 1;
+            self.pattern_shift_hi <<= 1;
+            self.attr_shift_lo <<= 1;
+            self.attr_shift_hi <<= 1;
+        }
+    }
+
+    // Palette index (0-3) and pixel (0-3) under the current dot, fine X picks the bit
+    fn background_pixel(&self, x: u16) -> (u8, u8) {
+        if !self.get_ppumask_show_background()
+            || (x < 8 && !self.get_ppumask_show_background_left())
+        {
+            return (0, 0);
+        }
+        let bit = 15 - self.x;
+        let pixel =
+            ((self.pattern_shift_lo >> bit) & 1) | (((self.pattern_shift_hi >> bit) & 1) << 1);
+        let palette = ((self.attr_shift_lo >> bit) & 1) | (((self.attr_shift_hi >> bit) & 1) << 1);
+
+        (palette as u8, pixel as u8)
+    }
+
+    // Fills secondary OAM with the first 8 sprites covering this scanline,
+    // see https://www.nesdev.org/wiki/PPU_sprite_evaluation
+    fn evaluate_sprites(&mut self, scanline: u16) {
+        let height = self.get_ppuctrl_sprite_height();
+        let in_range = |y: u8| scanline >= y as u16 && scanline < y as u16 + height;
+
+        self.secondary_oam.fill(0xFF);
+        self.sprite_count = 0;
+        self.sprite_zero_in_secondary_oam = false;
+
+        let mut n = 0;
+        while n < 64 && self.sprite_count < SPRITES_PER_SCANLINE {
+            if in_range(self.oam[n * 4]) {
+                let slot = self.sprite_count * 4;
+                self.secondary_oam[slot..slot + 4].copy_from_slice(&self.oam[n * 4..n * 4 + 4]);
+                self.sprite_count += 1;
+                if n == 0 {
+                    self.sprite_zero_in_secondary_oam = true;
+                }
+            }
+            n += 1;
+        }
+
+        // Hardware bug: once secondary OAM is full, the byte offset m is incremented along with n,
+        // so tile, attribute and X bytes get checked as if they were Y coordinates
+        let mut m = 0;
+        while n < 64 {
+            if in_range(self.oam[n * 4 + m]) {
+                self.set_ppustatus_sprite_overflow(true);
+                break;
+            }
+            n += 1;
+            m = (m + 1) % 4;
+        }
+    }
+
+    fn sprite_pattern_address(&self, sprite: usize, scanline: u16) -> u16 {
+        let [y, tile, attributes, _] = self.secondary_oam[sprite * 4..sprite * 4 + 4] else {
+            unreachable!()
+        };
+        let height = self.get_ppuctrl_sprite_height();
+        let mut row = scanline - y as u16;
+        if attributes & 0b1000_0000 != 0 {
+            row = height - 1 - row;
+        }
+
+        self.sprite_row_address(tile, row)
+    }
+
+    // Row 0-15 of a sprite's pattern, counted from the top of its unflipped tile
+    pub fn sprite_row_address(&self, tile: u8, row: u16) -> u16 {
+        if self.get_ppuctrl_sprite_height() == 16 {
+            // Top half in the even tile, bottom half in the next one
+            let table = (tile as u16 & 1) * 0x1000;
+            let tile = (tile as u16 & 0xFE) + row / 8;
+            table + tile * 16 + row % 8
+        } else {
+            self.get_ppuctrl_sprite_table() + tile as u16 * 16 + row
+        }
+    }
+
+    // Unused slots still fetch tile $FF, mappers watching the PPU address bus count on it
+    fn dummy_sprite_pattern_address(&self) -> u16 {
+        self.sprite_row_address(0xFF, 0)
+    }
+
+    // Moves the evaluated sprites over for the next scanline
+    fn load_sprite(&mut self, sprite: usize, pattern_lo: u8, pattern_hi: u8) {
+        let attributes = self.secondary_oam[sprite * 4 + 2];
+        let (pattern_lo, pattern_hi) = if attributes & 0b0100_0000 != 0 {
+            (pattern_lo.reverse_bits(), pattern_hi.reverse_bits())
+        } else {
+            (pattern_lo, pattern_hi)
+        };
+        self.sprite_pattern_lo[sprite] = pattern_lo;
+        self.sprite_pattern_hi[sprite] = pattern_hi;
+        self.sprite_attributes[sprite] = attributes;
+        self.sprite_x[sprite] = self.secondary_oam[sprite * 4 + 3];
+    }
+
+    // Front-most opaque sprite at screen x: index on the line, palette (4-7), pixel, behind background
+    fn sprite_pixel(&self, x: u16) -> Option<(usize, u8, u8, bool)> {
+        if !self.get_ppumask_show_sprites() || (x < 8 && !self.get_ppumask_show_sprites_left()) {
+            return None;
+        }
+        (0..self.sprite_count_line).find_map(|sprite| {
+            let offset = x.checked_sub(self.sprite_x[sprite] as u16)?;
+            if offset >= 8 {
+                return None;
+            }
+            let bit = 7 - offset;
+            let pixel = ((self.sprite_pattern_lo[sprite] >> bit) & 1)
+                | (((self.sprite_pattern_hi[sprite] >> bit) & 1) << 1);
+            if pixel == 0 {
+                return None;
+            }
+            let attributes = self.sprite_attributes[sprite];
+
+            Some((
+                sprite,
+                4 + (attributes & 0b11),
+                pixel,
+                attributes & 0b0010_0000 != 0,
+            ))
+        })
+    }
+
+    fn next_dot(&mut self) {
+        self.dot += 1;
+        // With rendering on, odd NTSC frames jump from dot 339 of the pre-render line
+        // straight to the next frame
+        let skip_dot = self.region.skips_odd_frame_dot()
+            && self.odd_frame
+            && self.rendering_enabled()
+            && self.scanline == self.pre_render_scanline()
+            && self.dot == DOTS_PER_SCANLINE - 1;
+        if self.dot == DOTS_PER_SCANLINE || skip_dot {
+            self.dot = 0;
+            self.scanline = (self.scanline + 1) % self.region.scanlines();
+            if self.scanline == 0 {
+                self.frame_complete = true;
+                self.odd_frame = !self.odd_frame;
+                self.decay_open_bus();
+            }
         }
     }
 }
 
-const ppu_colors: [Color; 1] = [Color {
-    r: 255,
-    g: 255,
-    b: 255,
-    a: 255,
-}];
+// Background fetches follow https://www.nesdev.org/wiki/PPU_rendering,
+// one nametable, attribute and two pattern bytes every 8 dots
+fn background_cycle(memory: &mut Memory, scanline: u16, dot: u16) {
+    if (2..=257).contains(&dot) || (321..=337).contains(&dot) {
+        memory.ppu_registers.shift_background();
+
+        match (dot - 1) % 8 {
+            0 => {
+                memory.ppu_registers.load_background_shifters();
+                memory.ppu_registers.nametable_latch =
+                    memory.ppu_get(memory.ppu_registers.nametable_address());
+            }
+            2 => {
+                let attr_byte = memory.ppu_get(memory.ppu_registers.attr_table_address());
+                memory.ppu_registers.set_attr_table_latch(attr_byte);
+            }
+            4 => {
+                memory.ppu_registers.pattern_table_lo_latch =
+                    memory.ppu_get(memory.ppu_registers.pattern_table_address());
+            }
+            6 => {
+                memory.ppu_registers.pattern_table_hi_latch =
+                    memory.ppu_get(memory.ppu_registers.pattern_table_address() + 8);
+            }
+            7 => memory.ppu_registers.increment_coarse_x(),
+            _ => {}
+        }
+    }
+    // Dot 1 fetches tile 3's nametable byte, which dot 337 already latched as the first of two
+    // unused fetches at the end of the line. Reading again keeps the PPU bus pattern right
+    if dot == 1 || dot == 339 {
+        memory.ppu_registers.nametable_latch =
+            memory.ppu_get(memory.ppu_registers.nametable_address());
+    }
 
-pub fn ppu_cycle(memory: &mut Memory, cycle: u64, d: &mut RaylibDrawHandle) {
-    let scanline = cycle / 341;
-    let pixel = cycle % 341;
+    if dot == 256 {
+        memory.ppu_registers.increment_y();
+    }
+    if dot == 257 {
+        memory.ppu_registers.load_background_shifters();
+        memory.ppu_registers.transfer_x();
+    }
+    if scanline == memory.ppu_registers.pre_render_scanline() && (280..=304).contains(&dot) {
+        memory.ppu_registers.transfer_y();
+    }
+}
 
-    if pixel == 0 {
+// Evaluation and pattern fetches happen in one go at dot 257 instead of spread over dots 65-320
+fn sprite_cycle(memory: &mut Memory, scanline: u16, dot: u16) {
+    if dot != 257 {
         return;
     }
 
-    let tile_fine_x = pixel % 8;
+    // Nothing is evaluated on the pre-render line, scanline 0 never has sprites, but all
+    // 8 slots are still fetched
+    if scanline == memory.ppu_registers.pre_render_scanline() {
+        memory.ppu_registers.sprite_count = 0;
+        memory.ppu_registers.sprite_zero_in_secondary_oam = false;
+    } else {
+        memory.ppu_registers.evaluate_sprites(scanline);
+    }
 
-    if tile_fine_x == 0 {
-        memory.ppu_registers.draw_nametable = memory.ppu_registers.nametable_latch;
-        memory.ppu_registers.draw_pattern_table_hi = memory.ppu_registers.pattern_table_hi_latch;
-        memory.ppu_registers.draw_pattern_table_lo = memory.ppu_registers.pattern_table_lo_latch;
-        memory.ppu_registers.draw_attr_table = memory.ppu_registers.attr_table_latch;
-    } else if tile_fine_x == 1 {
-        memory.ppu_registers.nametable_latch =
-            memory.ppu_get(((scanline / 8) * 32 + pixel / 8) as u16);
-    } else if tile_fine_x == 3 {
-        memory.ppu_registers.attr_table_latch =
-            memory.ppu_get((0x23C0 + (scanline / 32) * 8 + pixel / 32) as u16);
-    } else if tile_fine_x == 5 {
-        memory.ppu_registers.pattern_table_lo_latch =
-            memory.ppu_get(memory.ppu_registers.nametable_latch as u16);
-    } else if tile_fine_x == 7 {
-        memory.ppu_registers.pattern_table_hi_latch =
-            memory.ppu_get(memory.ppu_registers.nametable_latch as u16 + 8);
-    }
-
-    let pixel_data = ((memory.ppu_registers.draw_pattern_table_lo >> tile_fine_x) & 1)
-        + ((memory.ppu_registers.draw_pattern_table_hi >> tile_fine_x) & 1) * 2;
-    let palette_color = memory
-        .ppu_get(0x3f00 + memory.ppu_registers.draw_attr_table as u16 * 4 + pixel_data as u16);
-    let debug_color: u32 = (palette_color as u32).wrapping_mul(364353);
-
-    //let debug_color = scanline * 256 + pixel;
-
-    //println!("{} {} {} {} {}", (pixel * 2) as i32, (scanline * 2) as i32, (debug_color % 256) as u8, ((debug_color / 256) % 256) as u8, ((debug_color / 256 / 256) % 256) as u8);
-    d.draw_rectangle(
-        (pixel * 2) as i32,
-        (scanline * 2) as i32,
-        2,
-        2,
-        Color {
-            r: (debug_color % 256) as u8,
-            g: ((debug_color / 256) % 256) as u8,
-            b: ((debug_color / 256 / 256) % 256) as u8,
-            a: 255,
-        },
-    );
+    let sprite_count = memory.ppu_registers.sprite_count;
+    for sprite in 0..sprite_count {
+        let address = memory
+            .ppu_registers
+            .sprite_pattern_address(sprite, scanline);
+        let pattern_lo = memory.ppu_get(address);
+        let pattern_hi = memory.ppu_get(address + 8);
+        memory
+            .ppu_registers
+            .load_sprite(sprite, pattern_lo, pattern_hi);
+    }
+    for _ in sprite_count..SPRITES_PER_SCANLINE {
+        let address = memory.ppu_registers.dummy_sprite_pattern_address();
+        memory.ppu_get(address);
+        memory.ppu_get(address + 8);
+    }
+    memory.ppu_registers.sprite_count_line = sprite_count;
+    memory.ppu_registers.sprite_zero_on_line = memory.ppu_registers.sprite_zero_in_secondary_oam;
+}
+
+pub fn ppu_cycle(memory: &mut Memory) {
+    let scanline = memory.ppu_registers.scanline;
+    let dot = memory.ppu_registers.dot;
+    memory.ppu_registers.next_dot();
+
+    let pre_render_scanline = memory.ppu_registers.pre_render_scanline();
+    if dot == 1 {
+        if scanline == memory.ppu_registers.vblank_scanline() {
+            memory.ppu_registers.start_vblank();
+        } else if scanline == pre_render_scanline {
+            memory.ppu_registers.end_vblank();
+        }
+    }
+
+    if (scanline < 240 || scanline == pre_render_scanline)
+        && memory.ppu_registers.rendering_enabled()
+    {
+        background_cycle(memory, scanline, dot);
+        sprite_cycle(memory, scanline, dot);
+    }
+
+    if scanline >= 240 || !(1..=256).contains(&dot) {
+        return;
+    }
+
+    // Clipped pixels count as transparent, so sprite 0 can't hit in a clipped column either
+    let (background_palette, background_pixel) = memory.ppu_registers.background_pixel(dot - 1);
+    let (palette_number, pixel) = match memory.ppu_registers.sprite_pixel(dot - 1) {
+        Some((sprite, sprite_palette, sprite_pixel, behind_background)) => {
+            // Sprite 0 hit needs both pixels opaque, and never triggers at x=255
+            if sprite == 0
+                && memory.ppu_registers.sprite_zero_on_line
+                && background_pixel != 0
+                && dot != 256
+            {
+                memory.ppu_registers.set_ppustatus_sprite_zero_hit(true);
+            }
+            if behind_background && background_pixel != 0 {
+                (background_palette, background_pixel)
+            } else {
+                (sprite_palette, sprite_pixel)
+            }
+        }
+        None => (background_palette, background_pixel),
+    };
+    // Pixel 0 of every palette shows the backdrop color at $3F00. With rendering off the backdrop
+    // is replaced by whatever palette entry v points at, some games draw with that
+    let data_address = memory.ppu_registers.data_address();
+    let palette_address = if pixel != 0 {
+        0x3F00 + palette_number as u16 * 4 + pixel as u16
+    } else if !memory.ppu_registers.rendering_enabled() && data_address >= 0x3F00 {
+        data_address
+    } else {
+        0x3F00
+    };
+    let mut palette_color = memory.ppu_get(palette_address) & 0x3F;
+    // Greyscale keeps the luma bits and picks the grey column
+    if memory.ppu_registers.get_ppumask_greyscale() {
+        palette_color &= 0x30;
+    }
+    let emphasis = memory.ppu_registers.get_ppumask_emphasis();
+    let index = scanline as usize * SCREEN_WIDTH + (dot - 1) as usize;
+    memory.ppu_registers.framebuffer[index] = ((emphasis as u16) << 6) | palette_color as u16;
 }
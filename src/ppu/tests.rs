@@ -0,0 +1,391 @@
+use crate::memory::Memory;
+use crate::ppu::{OPEN_BUS_DECAY_FRAMES, PPURegisters, SCREEN_WIDTH, ppu_cycle};
+use crate::region::Region;
+
+// The worked example from https://www.nesdev.org/wiki/PPU_scrolling
+#[test]
+fn scroll_registers() {
+    let mut ppu = PPURegisters::new();
+    ppu.set(0x2000, 0x00);
+    ppu.get(0x2002);
+    assert!(!ppu.w);
+
+    ppu.set(0x2005, 0x7D);
+    assert_eq!((ppu.t, ppu.x, ppu.w), (0x000F, 0b101, true));
+    ppu.set(0x2005, 0x5E);
+    assert_eq!((ppu.t, ppu.w), (0x616F, false));
+    assert_eq!(ppu.scroll_origin(), (0x7D, 0x5E));
+    ppu.set(0x2006, 0x3D);
+    assert_eq!((ppu.t, ppu.w), (0x3D6F, true));
+    ppu.set(0x2006, 0xF0);
+    assert_eq!((ppu.t, ppu.v, ppu.w), (0x3DF0, 0x3DF0, false));
+}
+
+#[test]
+fn scroll_increments() {
+    let mut ppu = PPURegisters::new();
+
+    // Coarse X 31 wraps into the next horizontal nametable
+    ppu.v = 0x001F;
+    ppu.increment_coarse_x();
+    assert_eq!(ppu.v, 0x0400);
+
+    // Fine Y 7 on row 29 wraps into the next vertical nametable
+    ppu.v = 0x7000 | (29 << 5);
+    ppu.increment_y();
+    assert_eq!(ppu.v, 0x0800);
+
+    // Row 31 wraps to 0 without switching nametables
+    ppu.v = 0x7000 | (31 << 5);
+    ppu.increment_y();
+    assert_eq!(ppu.v, 0x0000);
+
+    ppu.t = 0x7FFF;
+    ppu.v = 0;
+    ppu.transfer_x();
+    assert_eq!(ppu.v, 0x041F);
+    ppu.transfer_y();
+    assert_eq!(ppu.v, 0x7FFF);
+}
+
+#[test]
+fn attribute_quadrant() {
+    let mut ppu = PPURegisters::new();
+    // Coarse X 2, coarse Y 2 is the bottom right quadrant of attribute byte 0
+    ppu.v = (2 << 5) | 2;
+    assert_eq!(ppu.attr_table_address(), 0x23C0);
+    ppu.set_attr_table_latch(0b1100_0000);
+    assert_eq!(ppu.attr_table_latch, 0b11);
+
+    ppu.v = (4 << 5) | 4 | 0x0400;
+    assert_eq!(ppu.attr_table_address(), 0x27C9);
+}
+
+#[test]
+fn oam_registers() {
+    let mut ppu = PPURegisters::new();
+    ppu.set(0x2003, 0xFF);
+    ppu.set(0x2004, 0x12);
+    ppu.set(0x2004, 0x34);
+    assert_eq!((ppu.oam[0xFF], ppu.oam[0x00]), (0x12, 0x34));
+    assert_eq!(ppu.get(0x2004), ppu.oam[0x01]);
+    assert_eq!(ppu.oamaddr, 0x01);
+}
+
+#[test]
+fn sprite_overflow_bug() {
+    let mut ppu = PPURegisters::new();
+    ppu.ppustatus = 0;
+    ppu.oam.fill(0xFF);
+    for sprite in 0..8 {
+        ppu.oam[sprite * 4] = 10;
+    }
+
+    ppu.evaluate_sprites(12);
+    assert_eq!(ppu.sprite_count, 8);
+    assert!(ppu.sprite_zero_in_secondary_oam);
+    assert_eq!(ppu.ppustatus & 0b0010_0000, 0);
+
+    // A real ninth sprite is missed because the tile byte gets checked instead of Y
+    ppu.oam[9 * 4] = 10;
+    ppu.evaluate_sprites(12);
+    assert_eq!(ppu.ppustatus & 0b0010_0000, 0);
+
+    // And a tile index that happens to look in range trips the flag
+    ppu.oam[9 * 4 + 1] = 5;
+    ppu.evaluate_sprites(12);
+    assert_ne!(ppu.ppustatus & 0b0010_0000, 0);
+}
+
+#[test]
+fn sprite_patterns() {
+    let mut ppu = PPURegisters::new();
+    ppu.oam.fill(0xFF);
+    // Vertically and horizontally flipped 8x8 sprite using the $1000 table
+    ppu.oam[0..4].copy_from_slice(&[20, 0x42, 0b1100_0001, 100]);
+    ppu.set(0x2000, 0b0000_1000);
+    ppu.evaluate_sprites(21);
+    assert_eq!(ppu.sprite_pattern_address(0, 21), 0x1000 + 0x42 * 16 + 6);
+
+    ppu.load_sprite(0, 0b1000_0000, 0b1100_0000);
+    ppu.sprite_count_line = 1;
+    ppu.set(0x2001, 0b0001_0000);
+    assert_eq!(ppu.sprite_pixel(99), None);
+    assert_eq!(ppu.sprite_pixel(107), Some((0, 5, 3, false)));
+    assert_eq!(ppu.sprite_pixel(106), Some((0, 5, 2, false)));
+
+    // 8x16 sprites take the table from bit 0 of the tile, the bottom half is the next tile
+    ppu.oam[0..4].copy_from_slice(&[20, 0x43, 0, 100]);
+    ppu.set(0x2000, 0b0010_0000);
+    ppu.evaluate_sprites(30);
+    assert_eq!(ppu.sprite_pattern_address(0, 30), 0x1000 + 0x43 * 16 + 2);
+}
+
+#[test]
+fn vblank_and_nmi() {
+    let mut ppu = PPURegisters::new();
+    ppu.ppustatus = 0b0110_0000;
+    ppu.start_vblank();
+    assert!(!ppu.nmi_output());
+
+    // Enabling NMI during vblank raises the line right away
+    ppu.set(0x2000, 0b1000_0000);
+    assert!(ppu.nmi_output());
+
+    // Unused bits come from whatever was last on the bus
+    ppu.set(0x2000, 0b1001_0101);
+    assert_eq!(ppu.get(0x2002), 0b1111_0101);
+    assert!(!ppu.nmi_output());
+
+    ppu.start_vblank();
+    ppu.end_vblank();
+    assert_eq!(ppu.ppustatus & 0b1110_0000, 0);
+}
+
+#[test]
+fn vblank_read_race() {
+    let mut ppu = PPURegisters::new();
+    ppu.ppustatus = 0;
+    ppu.set(0x2000, 0b1000_0000);
+
+    // A read the dot before vblank starts sees it clear and cancels it for the frame
+    ppu.scanline = 241;
+    ppu.dot = 1;
+    assert_eq!(ppu.get(0x2002) & 0b1000_0000, 0);
+    ppu.start_vblank();
+    assert!(!ppu.nmi_output());
+
+    // The next frame is unaffected
+    ppu.start_vblank();
+    assert!(ppu.nmi_output());
+}
+
+#[test]
+fn open_bus() {
+    let mut ppu = PPURegisters::new();
+    ppu.set(0x2001, 0b0101_1010);
+    assert_eq!(ppu.get(0x2000), 0b0101_1010);
+    assert_eq!(ppu.get(0x2005), 0b0101_1010);
+
+    // PPUSTATUS only drives its top 3 bits, so only those get refreshed
+    ppu.ppustatus = 0b1110_0000;
+    for _ in 0..OPEN_BUS_DECAY_FRAMES - 1 {
+        ppu.decay_open_bus();
+    }
+    ppu.get(0x2002);
+    ppu.decay_open_bus();
+    assert_eq!(ppu.get(0x2000), 0b1110_0000);
+
+    // Attribute bits 2-4 are not stored
+    ppu.set(0x2003, 0x02);
+    ppu.set(0x2004, 0xFF);
+    ppu.set(0x2003, 0x02);
+    assert_eq!(ppu.get(0x2004), 0b1110_0011);
+}
+
+#[test]
+fn left_column_clipping() {
+    let mut ppu = PPURegisters::new();
+    ppu.pattern_shift_lo = 0xFFFF;
+    ppu.secondary_oam[0..4].copy_from_slice(&[0, 0, 0, 0]);
+    ppu.load_sprite(0, 0xFF, 0x00);
+    ppu.sprite_count_line = 1;
+
+    ppu.set(0x2001, 0b0001_1000);
+    assert_eq!(ppu.background_pixel(7), (0, 0));
+    assert_eq!(ppu.sprite_pixel(7), None);
+    assert_eq!(ppu.background_pixel(8), (0, 1));
+
+    ppu.set(0x2001, 0b1111_1110);
+    assert_eq!(ppu.background_pixel(7), (0, 1));
+    assert_eq!(ppu.sprite_pixel(7), Some((0, 4, 1, false)));
+    assert_eq!(ppu.get_ppumask_emphasis(), 0b111);
+}
+
+// Dots it took to finish the frame
+fn run_frame(memory: &mut Memory) -> usize {
+    let mut dots = 1;
+    ppu_cycle(memory);
+    while !memory.ppu_registers.take_frame_complete() {
+        ppu_cycle(memory);
+        dots += 1;
+    }
+
+    dots
+}
+
+#[test]
+fn background_frame() {
+    // Tile 1 is solid color 1
+    let mut chr_rom = vec![0; 0x2000];
+    chr_rom[16..24].fill(0xFF);
+    let mut memory = Memory::with_prg(vec![0; 0x4000]).with_chr_rom(chr_rom);
+    let write = |memory: &mut Memory, address: u16, value: u8| {
+        memory.set(0x2006, (address >> 8) as u8);
+        memory.set(0x2006, address as u8);
+        memory.set(0x2007, value);
+    };
+    write(&mut memory, 0x2000, 0x01);
+    write(&mut memory, 0x3F00, 0x0F);
+    write(&mut memory, 0x3F01, 0x16);
+    // PPUADDR left its nametable bits in t, reset them like a game would
+    memory.set(0x2000, 0);
+    memory.set(0x2005, 0);
+    memory.set(0x2005, 0);
+    memory.set(0x2001, 0b0000_1010);
+
+    // The first frame starts without the pre-render line's fetches
+    run_frame(&mut memory);
+    run_frame(&mut memory);
+    let framebuffer = memory.ppu_registers.framebuffer();
+    assert_eq!(framebuffer[..8], [0x16; 8]);
+    assert_eq!(framebuffer[8..16], [0x0F; 8]);
+    assert_eq!(
+        framebuffer[7 * SCREEN_WIDTH..7 * SCREEN_WIDTH + 9],
+        [0x16, 0x16, 0x16, 0x16, 0x16, 0x16, 0x16, 0x16, 0x0F]
+    );
+    assert_eq!(framebuffer[8 * SCREEN_WIDTH], 0x0F);
+
+    // Fine X scroll moves the tile left
+    memory.set(0x2005, 4);
+    memory.set(0x2005, 0);
+    run_frame(&mut memory);
+    let framebuffer = memory.ppu_registers.framebuffer();
+    assert_eq!(framebuffer[..5], [0x16, 0x16, 0x16, 0x16, 0x0F]);
+
+    // Red emphasis and greyscale go into the index
+    memory.set(0x2001, 0b0010_1011);
+    run_frame(&mut memory);
+    assert_eq!(memory.ppu_registers.framebuffer()[0], 0b001 << 6 | 0x10);
+}
+
+// Scanline and dot that set sprite 0 hit during the next frame, if any did
+fn sprite_zero_hit(memory: &mut Memory) -> Option<(u16, u16)> {
+    let mut hit = None;
+    loop {
+        let position = memory.ppu_registers.position();
+        ppu_cycle(memory);
+        if hit.is_none() && memory.ppu_registers.ppustatus & 0b0100_0000 != 0 {
+            hit = Some(position);
+        }
+        if memory.ppu_registers.take_frame_complete() {
+            return hit;
+        }
+    }
+}
+
+#[test]
+fn sprite_zero_and_priority() {
+    // Tile 1 is solid color 1, tile 2 solid color 3
+    let mut chr_rom = vec![0; 0x2000];
+    chr_rom[16..24].fill(0xFF);
+    chr_rom[32..48].fill(0xFF);
+    let mut memory = Memory::with_prg(vec![0; 0x4000]).with_chr_rom(chr_rom);
+    let write = |memory: &mut Memory, address: u16, value: u8| {
+        memory.set(0x2006, (address >> 8) as u8);
+        memory.set(0x2006, address as u8);
+        memory.set(0x2007, value);
+    };
+    // Opaque background tiles on row 2, lines 16-23, in columns 0, 4, 5 and 31
+    for column in [0, 4, 5, 31] {
+        write(&mut memory, 0x2040 + column, 0x01);
+    }
+    write(&mut memory, 0x3F00, 0x0F);
+    write(&mut memory, 0x3F01, 0x16);
+    write(&mut memory, 0x3F13, 0x2A);
+    write(&mut memory, 0x3F17, 0x30);
+    memory.set(0x2000, 0);
+    memory.set(0x2005, 0);
+    memory.set(0x2005, 0);
+    // Sprite 0 in front over column 4, sprite 1 behind the background half over column 5.
+    // Sprites show up the line after their Y
+    memory.ppu_registers.oam.fill(0xFF);
+    memory.ppu_registers.oam[..8].copy_from_slice(&[15, 0x02, 0x00, 32, 15, 0x02, 0x21, 44]);
+    memory.set(0x2001, 0b0001_1110);
+
+    run_frame(&mut memory);
+    assert_eq!(sprite_zero_hit(&mut memory), Some((16, 33)));
+    let line = &memory.ppu_registers.framebuffer()[16 * SCREEN_WIDTH..17 * SCREEN_WIDTH];
+    assert_eq!(line[31..33], [0x0F, 0x2A]);
+    assert_eq!(line[39..41], [0x2A, 0x16]);
+    // The background wins over the behind sprite only where it's opaque
+    assert_eq!(
+        line[43..53],
+        [0x16, 0x16, 0x16, 0x16, 0x16, 0x30, 0x30, 0x30, 0x30, 0x0F]
+    );
+    let line = &memory.ppu_registers.framebuffer()[24 * SCREEN_WIDTH..25 * SCREEN_WIDTH];
+    assert_eq!(line[32], 0x0F);
+
+    // Only x=255 overlaps the background there, which never hits
+    memory.ppu_registers.oam[3] = 255;
+    assert_eq!(sprite_zero_hit(&mut memory), None);
+    assert_eq!(
+        memory.ppu_registers.framebuffer()[16 * SCREEN_WIDTH + 255],
+        0x2A
+    );
+
+    // Clipped columns count as transparent
+    memory.ppu_registers.oam[3] = 0;
+    memory.set(0x2001, 0b0001_1000);
+    assert_eq!(sprite_zero_hit(&mut memory), None);
+    assert_eq!(memory.ppu_registers.framebuffer()[16 * SCREEN_WIDTH], 0x0F);
+    memory.set(0x2001, 0b0001_1110);
+    assert_eq!(sprite_zero_hit(&mut memory), Some((16, 1)));
+
+    // The flag stays up through vblank and clears on the pre-render line
+    let run_to = |memory: &mut Memory, position: (u16, u16)| {
+        while memory.ppu_registers.position() != position {
+            ppu_cycle(memory);
+        }
+    };
+    run_to(&mut memory, (241, 2));
+    assert_ne!(memory.ppu_registers.ppustatus & 0b0100_0000, 0);
+    run_to(&mut memory, (261, 1));
+    assert_ne!(memory.ppu_registers.ppustatus & 0b0100_0000, 0);
+    ppu_cycle(&mut memory);
+    assert_eq!(memory.ppu_registers.ppustatus & 0b0100_0000, 0);
+}
+
+#[test]
+fn pal_frame() {
+    let mut memory = Memory::with_prg(vec![0; 0x4000]).with_chr_rom(vec![0; 0x2000]);
+    memory.ppu_registers.set_region(Region::Pal);
+
+    // 70 lines of vblank before the pre-render line at 311
+    let run = |memory: &mut Memory, dots: usize| {
+        for _ in 0..dots {
+            ppu_cycle(memory);
+        }
+    };
+    run(&mut memory, 341 * 242);
+    assert!(memory.ppu_registers.get_ppustatus_vblank());
+    run(&mut memory, 341 * (311 - 242));
+    assert_eq!(memory.ppu_registers.position(), (311, 0));
+    assert!(memory.ppu_registers.get_ppustatus_vblank());
+    run(&mut memory, 2);
+    assert!(!memory.ppu_registers.get_ppustatus_vblank());
+    run(&mut memory, 341 - 2);
+    assert!(memory.ppu_registers.take_frame_complete());
+    assert_eq!(memory.ppu_registers.position(), (0, 0));
+
+    // Red and green emphasis trade places
+    memory.set(0x2001, 0b0010_0000);
+    assert_eq!(memory.ppu_registers.get_ppumask_emphasis(), 0b010);
+}
+
+#[test]
+fn odd_frame_skip() {
+    let mut memory = Memory::with_prg(vec![0; 0x4000]).with_chr_rom(vec![0; 0x2000]);
+    assert_eq!(run_frame(&mut memory), 341 * 262);
+    assert_eq!(run_frame(&mut memory), 341 * 262);
+
+    // Only odd frames with rendering on lose a dot
+    memory.set(0x2001, 0b0000_1000);
+    assert_eq!(run_frame(&mut memory), 341 * 262);
+    assert_eq!(run_frame(&mut memory), 341 * 262 - 1);
+    assert_eq!(run_frame(&mut memory), 341 * 262);
+
+    memory.ppu_registers.set_region(Region::Pal);
+    assert_eq!(run_frame(&mut memory), 341 * 312);
+    assert_eq!(run_frame(&mut memory), 341 * 312);
+}
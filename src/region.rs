@@ -0,0 +1,100 @@
+use crate::rom_reader::Timing;
+
+#[cfg(test)]
+mod tests;
+
+// Console timing variants, see https://www.nesdev.org/wiki/Cycle_reference_chart
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Ntsc,
+    Pal,
+    // Famiclones pairing a PAL-speed PPU with NTSC-like frame timing
+    Dendy,
+}
+
+impl Region {
+    // Multi-region games run as NTSC
+    pub fn from_timing(timing: Timing) -> Region {
+        match timing {
+            Timing::Ntsc | Timing::MultiRegion => Region::Ntsc,
+            Timing::Pal => Region::Pal,
+            Timing::Dendy => Region::Dendy,
+        }
+    }
+
+    // Master clock cycles per CPU cycle
+    pub fn cpu_divider(self) -> u64 {
+        match self {
+            Region::Ntsc => 12,
+            Region::Pal => 16,
+            Region::Dendy => 15,
+        }
+    }
+
+    // Master clock cycles per PPU dot
+    pub fn ppu_divider(self) -> u64 {
+        match self {
+            Region::Ntsc => 4,
+            Region::Pal | Region::Dendy => 5,
+        }
+    }
+
+    // Scanlines per frame, the last one is the pre-render line
+    pub fn scanlines(self) -> u16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    // PAL has 70 lines of vblank, Dendy keeps NTSC's 20 and idles after the picture instead
+    pub fn vblank_scanline(self) -> u16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    // Only the 2C02 shortens odd frames by a dot
+    pub fn skips_odd_frame_dot(self) -> bool {
+        self == Region::Ntsc
+    }
+
+    // The 2C07 and its clones wire PPUMASK bit 5 to green and bit 6 to red
+    pub fn swaps_emphasis(self) -> bool {
+        self != Region::Ntsc
+    }
+
+    // CPU cycles to each step of the APU frame counter's 5-step sequence,
+    // the 4-step one ends at the fourth. Dendy's APU keeps NTSC timing
+    pub fn frame_counter_steps(self) -> [u64; 5] {
+        match self {
+            Region::Ntsc | Region::Dendy => [7457, 14913, 22371, 29829, 37281],
+            Region::Pal => [8313, 16627, 24939, 33253, 41565],
+        }
+    }
+
+    // Noise channel timer periods in CPU cycles, indexed by $400E bits 0-3
+    pub fn noise_periods(self) -> [u16; 16] {
+        match self {
+            Region::Ntsc | Region::Dendy => [
+                4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+            ],
+            Region::Pal => [
+                4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+            ],
+        }
+    }
+
+    // DMC output rates in CPU cycles, indexed by $4010 bits 0-3
+    pub fn dmc_rates(self) -> [u16; 16] {
+        match self {
+            Region::Ntsc | Region::Dendy => [
+                428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+            ],
+            Region::Pal => [
+                398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+            ],
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use crate::region::Region;
+
+// CPU clock in Hz for each region, the master clock divided down
+fn cpu_clock(region: Region) -> f64 {
+    let master_clock = match region {
+        Region::Ntsc => 236_250_000.0 / 11.0,
+        Region::Pal | Region::Dendy => 26_601_712.0,
+    };
+    master_clock / region.cpu_divider() as f64
+}
+
+#[test]
+fn frame_counter() {
+    // The 4-step sequence runs at 60 Hz on NTSC and 50 Hz on PAL, rounded to whole cycles
+    for (region, rate) in [(Region::Ntsc, 60.0), (Region::Pal, 50.0)] {
+        let steps = region.frame_counter_steps();
+        assert!((steps[3] as f64 - cpu_clock(region) / rate).abs() < 1.0);
+        assert!(steps.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+    assert_eq!(
+        Region::Dendy.frame_counter_steps(),
+        Region::Ntsc.frame_counter_steps()
+    );
+}
+
+#[test]
+fn channel_periods() {
+    // The noise and DMC timers tick every APU cycle, 2 CPU cycles
+    for region in [Region::Ntsc, Region::Pal] {
+        let noise = region.noise_periods();
+        assert!(noise.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(noise.iter().all(|period| period % 2 == 0));
+        let dmc = region.dmc_rates();
+        assert!(dmc.windows(2).all(|pair| pair[0] > pair[1]));
+        assert!(dmc.iter().all(|rate| rate % 2 == 0));
+    }
+
+    // PAL noise keeps the NTSC pitches on its slower clock
+    let ratio = cpu_clock(Region::Pal) / cpu_clock(Region::Ntsc);
+    for (ntsc, pal) in Region::Ntsc
+        .noise_periods()
+        .iter()
+        .zip(Region::Pal.noise_periods())
+    {
+        assert!((*ntsc as f64 * ratio - pal as f64).abs() < 2.0);
+    }
+    // The fastest DMC rate is about 33 kHz on both
+    assert_eq!(Region::Ntsc.dmc_rates()[15], 54);
+    assert_eq!(Region::Pal.dmc_rates()[15], 50);
+
+    assert_eq!(Region::Dendy.noise_periods(), Region::Ntsc.noise_periods());
+    assert_eq!(Region::Dendy.dmc_rates(), Region::Ntsc.dmc_rates());
+}
@@ -1,7 +1,78 @@
+mod archive;
+mod bps;
+pub mod database;
+pub mod fds;
+pub mod ips;
+pub mod nsf;
+#[cfg(test)]
+mod tests;
+mod unif;
+mod ups;
+
+use std::path::{Path, PathBuf};
+
+use crate::checksum;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    SingleScreenLower,
+    SingleScreenUpper,
+    FourScreen,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timing {
+    Ntsc,
+    Pal,
+    MultiRegion,
+    Dendy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem,
+    Playchoice10,
+    // NES 2.0 byte 13 low nibble: Famiclone with decimal mode, VT0x, etc.
+    Extended(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderFormat {
+    // Bytes 7-15 are garbage (e.g. "DiskDude!"), only bytes 4-6 are trusted
+    Archaic,
+    INes,
+    Nes20,
+    // Synthesized from UNIF chunks
+    Unif,
+    // Famicom Disk System image, the cartridge is the RAM adapter
+    Fds,
+    // NSF/NSFe music rip, played back rather than run
+    Nsf,
+}
+
+// All sizes are in bytes
 #[allow(non_camel_case_types)]
+#[derive(Debug, Clone)]
 pub struct iNES_header {
-    pub prg_rom_size: u8,
-    pub chr_rom_size: u8,
+    pub format: HeaderFormat,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub mirroring: Mirroring,
+    pub battery: bool,
+    pub trainer: bool,
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub timing: Timing,
+    pub console_type: ConsoleType,
+    pub misc_roms: u8,
+    pub expansion_device: u8,
 }
 
 #[allow(non_camel_case_types)]
@@ -10,65 +81,410 @@ pub struct iNES {
     pub trainer: Vec<u8>,
     pub prg_rom: Vec<u8>,
     pub chr_rom: Vec<u8>,
+    // Checksums of PRG-ROM + CHR-ROM, the usual key for ROM databases
+    pub crc32: u32,
+    pub sha1: [u8; 20],
+    pub database_name: Option<String>,
+    // UNIF board name, iNES only has mapper numbers
+    pub board: Option<String>,
+    // FDS disk sides in fwNES layout, empty for cartridges
+    pub disk_sides: Vec<Vec<u8>>,
+    pub nsf: Option<nsf::NsfInfo>,
 }
 
-pub fn read_file(filename: &str) -> iNES {
-    let file = std::fs::read(filename).unwrap();
-    assert!(file[0..4] == vec!['N' as u8, 'E' as u8, 'S' as u8, 0x1A]);
-    let ines_header = iNES_header {
-        prg_rom_size: file[4],
-        chr_rom_size: file[5],
+// NROM for now. The FDS RAM adapter and NSF bankswitching only come with their own
+// formats, cartridges using them aren't emulated
+fn is_supported(header: &iNES_header) -> bool {
+    match header.mapper {
+        0 => true,
+        20 => header.format == HeaderFormat::Fds,
+        31 => header.format == HeaderFormat::Nsf,
+        _ => false,
+    }
+}
+
+#[derive(Debug)]
+pub enum RomError {
+    MissingFile(String),
+    Io(std::io::Error),
+    BadMagic,
+    BadHeader(String),
+    UnsupportedBoard(String),
+    // A known UNIF board whose mapper isn't emulated
+    UnsupportedBoardMapper {
+        board: String,
+        mapper: u16,
+    },
+    Truncated {
+        section: &'static str,
+        expected: usize,
+        found: usize,
+    },
+    UnsupportedMapper(u16),
+    BadBios(usize),
+    MissingBios,
+    BadPatch(String),
+    PatchMismatch {
+        format: &'static str,
+        expected: u32,
+        found: u32,
+    },
+    BadNsf(String),
+    BadArchive(String),
+    UnsupportedArchive(String),
+    MissingEntry(String),
+    NoRomInArchive,
+}
+
+impl std::fmt::Display for RomError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RomError::MissingFile(filename) => write!(f, "ROM file not found: {filename}"),
+            RomError::Io(e) => write!(f, "Failed to read ROM: {e}"),
+            RomError::BadMagic => write!(
+                f,
+                "Unknown format, header does not start with NES<EOF>, UNIF, FDS<EOF>, NESM<EOF> or NSFE"
+            ),
+            RomError::BadHeader(reason) => write!(f, "Malformed header: {reason}"),
+            RomError::UnsupportedBoard(board) => write!(f, "Unsupported UNIF board {board}"),
+            RomError::UnsupportedBoardMapper { board, mapper } => write!(
+                f,
+                "UNIF board {board} is mapper {mapper}, which is not supported"
+            ),
+            RomError::Truncated {
+                section,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Truncated {section}: header declares {expected} bytes, only {found} left in file"
+            ),
+            RomError::UnsupportedMapper(mapper) => write!(f, "Unsupported mapper {mapper}"),
+            RomError::BadBios(size) => {
+                write!(f, "FDS BIOS must be {} bytes, got {size}", fds::BIOS_SIZE)
+            }
+            RomError::MissingBios => write!(f, "FDS images need the disk system BIOS loaded"),
+            RomError::BadPatch(format) => write!(f, "Malformed {format} patch"),
+            RomError::PatchMismatch {
+                format,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{format} patch is for a different ROM: expects CRC32 {expected:08X}, this one is {found:08X}"
+            ),
+            RomError::BadNsf(reason) => write!(f, "Malformed NSF: {reason}"),
+            RomError::BadArchive(reason) => write!(f, "Malformed archive: {reason}"),
+            RomError::UnsupportedArchive(what) => write!(f, "Unsupported archive: {what}"),
+            RomError::MissingEntry(name) => write!(f, "Archive has no entry named {name}"),
+            RomError::NoRomInArchive => write!(
+                f,
+                "Archive has no .nes, .fds, .nsf, .nsfe, .unf or .unif entry"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
+// NES 2.0 RAM sizes are shift counts, 64 << n bytes, 0 means none
+fn shift_to_size(shift: u8) -> usize {
+    if shift == 0 { 0 } else { 64 << shift }
+}
+
+// NES 2.0 ROM sizes, either in units or in exponent-multiplier notation when the MSB nibble is $F.
+// None when the exponent form doesn't fit in a usize
+fn rom_size(lsb: u8, msb: u8, unit: usize) -> Option<usize> {
+    if msb == 0x0F {
+        let exponent = lsb >> 2;
+        let multiplier = (lsb & 0b0000_0011) as usize * 2 + 1;
+        1usize.checked_shl(exponent as u32)?.checked_mul(multiplier)
+    } else {
+        Some((((msb as usize) << 8) | lsb as usize) * unit)
+    }
+}
+
+fn parse_header(header: &[u8; 16]) -> Result<iNES_header, RomError> {
+    let format = if header[7] & 0b0000_1100 == 0b0000_1000 {
+        HeaderFormat::Nes20
+    } else if header[7] & 0b0000_1100 == 0 && header[12..16].iter().all(|&b| b == 0) {
+        HeaderFormat::INes
+    } else {
+        HeaderFormat::Archaic
+    };
+
+    let mirroring = if header[6] & 0b0000_1000 != 0 {
+        Mirroring::FourScreen
+    } else if header[6] & 0b0000_0001 != 0 {
+        Mirroring::Vertical
+    } else {
+        Mirroring::Horizontal
     };
+    let battery = header[6] & 0b0000_0010 != 0;
+    let trainer = header[6] & 0b0000_0100 != 0;
+    let mapper_lo = (header[6] >> 4) as u16;
+
+    let console_type = |extended: u8| match header[7] & 0b0000_0011 {
+        0 => ConsoleType::Nes,
+        1 => ConsoleType::VsSystem,
+        2 => ConsoleType::Playchoice10,
+        _ => ConsoleType::Extended(extended),
+    };
+
+    let header = match format {
+        HeaderFormat::Nes20 => {
+            let too_large = |section: &str| {
+                RomError::BadHeader(format!("{section} size does not fit in memory"))
+            };
+            let prg_rom_size = rom_size(header[4], header[9] & 0x0F, 0x4000)
+                .ok_or_else(|| too_large("PRG-ROM"))?;
+            let chr_rom_size =
+                rom_size(header[5], header[9] >> 4, 0x2000).ok_or_else(|| too_large("CHR-ROM"))?;
+            iNES_header {
+                format,
+                prg_rom_size,
+                chr_rom_size,
+                mapper: mapper_lo | (header[7] & 0xF0) as u16 | ((header[8] & 0x0F) as u16) << 8,
+                submapper: header[8] >> 4,
+                mirroring,
+                battery,
+                trainer,
+                prg_ram_size: shift_to_size(header[10] & 0x0F),
+                prg_nvram_size: shift_to_size(header[10] >> 4),
+                chr_ram_size: shift_to_size(header[11] & 0x0F),
+                chr_nvram_size: shift_to_size(header[11] >> 4),
+                timing: match header[12] & 0b0000_0011 {
+                    0 => Timing::Ntsc,
+                    1 => Timing::Pal,
+                    2 => Timing::MultiRegion,
+                    _ => Timing::Dendy,
+                },
+                console_type: console_type(header[13] & 0x0F),
+                misc_roms: header[14] & 0b0000_0011,
+                expansion_device: header[15] & 0b0011_1111,
+            }
+        }
+        // parse_header only ever sees iNES headers
+        HeaderFormat::INes
+        | HeaderFormat::Archaic
+        | HeaderFormat::Unif
+        | HeaderFormat::Fds
+        | HeaderFormat::Nsf => {
+            let archaic = format == HeaderFormat::Archaic;
+            let chr_rom_size = header[5] as usize * 0x2000;
+            // Byte 8 in 8 KiB units, 0 infers 8 KiB for compatibility
+            let prg_ram_size = if archaic {
+                1
+            } else {
+                header[8].max(1) as usize
+            } * 0x2000;
+            iNES_header {
+                format,
+                prg_rom_size: header[4] as usize * 0x4000,
+                chr_rom_size,
+                mapper: if archaic {
+                    mapper_lo
+                } else {
+                    mapper_lo | (header[7] & 0xF0) as u16
+                },
+                submapper: 0,
+                mirroring,
+                battery,
+                trainer,
+                prg_ram_size: if battery { 0 } else { prg_ram_size },
+                prg_nvram_size: if battery { prg_ram_size } else { 0 },
+                // No CHR-RAM field, finish_loading fills it in for carts without CHR-ROM
+                chr_ram_size: 0,
+                chr_nvram_size: 0,
+                timing: if !archaic && header[9] & 0b0000_0001 != 0 {
+                    Timing::Pal
+                } else {
+                    Timing::Ntsc
+                },
+                console_type: if archaic {
+                    ConsoleType::Nes
+                } else {
+                    console_type(0)
+                },
+                misc_roms: 0,
+                expansion_device: 0,
+            }
+        }
+    };
+
+    Ok(header)
+}
+
+pub fn read_bytes(filename: &Path) -> Result<Vec<u8>, RomError> {
+    std::fs::read(filename).map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => RomError::MissingFile(filename.display().to_string()),
+        _ => RomError::Io(e),
+    })
+}
+
+// game.nes picks up game.ips, game.bps or game.ups sitting next to it
+fn same_named_patch(filename: &Path) -> Option<PathBuf> {
+    ["ips", "bps", "ups"]
+        .into_iter()
+        .map(|extension| filename.with_extension(extension))
+        .find(|patch| patch.is_file())
+}
+
+pub fn apply_patch(file: &[u8], patch: &[u8]) -> Result<Vec<u8>, RomError> {
+    if patch.starts_with(b"PATCH") {
+        ips::apply(file, patch)
+    } else if patch.starts_with(b"BPS1") {
+        bps::apply(file, patch)
+    } else if patch.starts_with(b"UPS1") {
+        ups::apply(file, patch)
+    } else {
+        Err(RomError::BadPatch("unknown".to_string()))
+    }
+}
+
+pub fn read_file(filename: &str) -> Result<iNES, RomError> {
+    read_file_with(filename, None, None)
+}
+
+// ROM entries of a .zip, .gz or .7z, empty for a plain ROM file
+pub fn archive_entries(file: &[u8]) -> Result<Vec<String>, RomError> {
+    if archive::is_archive(file) {
+        archive::entry_names(file)
+    } else {
+        Ok(Vec::new())
+    }
+}
+
+pub fn read_file_with(
+    filename: &str,
+    patch_filename: Option<&str>,
+    entry_name: Option<&str>,
+) -> Result<iNES, RomError> {
+    let file = read_bytes(Path::new(filename))?;
+    load_file(filename, file, patch_filename, entry_name)
+}
+
+// Archives are unpacked first, then patches are applied to the ROM in memory,
+// the files on disk are left alone. filename is only used to find a same-named patch
+pub fn load_file(
+    filename: &str,
+    mut file: Vec<u8>,
+    patch_filename: Option<&str>,
+    entry_name: Option<&str>,
+) -> Result<iNES, RomError> {
+    if archive::is_archive(&file) {
+        file = archive::extract(&file, entry_name)?;
+    }
+    let patch_filename = match patch_filename {
+        Some(patch_filename) => Some(PathBuf::from(patch_filename)),
+        None => same_named_patch(Path::new(filename)),
+    };
+    if let Some(patch_filename) = patch_filename {
+        file = apply_patch(&file, &read_bytes(&patch_filename)?)?;
+    }
+
+    parse_rom(&file)
+}
+
+pub fn parse_rom(file: &[u8]) -> Result<iNES, RomError> {
+    if file.starts_with(b"UNIF") {
+        unif::parse_unif(file)
+    } else if fds::is_disk_image(file) {
+        fds::parse_fds(file)
+    } else if nsf::is_nsf(file) {
+        nsf::parse_nsf(file)
+    } else {
+        parse_ines(file)
+    }
+}
+
+fn read_section<'a>(
+    file: &'a [u8],
+    pointer: &mut usize,
+    size: usize,
+    section: &'static str,
+) -> Result<&'a [u8], RomError> {
+    let available = file.len().saturating_sub(*pointer);
+    if available < size {
+        return Err(RomError::Truncated {
+            section,
+            expected: size,
+            found: available,
+        });
+    }
+    let data = &file[*pointer..*pointer + size];
+    *pointer += size;
+
+    Ok(data)
+}
+
+pub fn parse_ines(file: &[u8]) -> Result<iNES, RomError> {
+    if file.len() < 16 || file[0..4] != *b"NES\x1A" {
+        return Err(RomError::BadMagic);
+    }
+    let ines_header = parse_header(file[0..16].try_into().unwrap())?;
+
     let mut pointer = 16;
     let mut trainer = vec![];
-    if file[6] & 0b0000_0100 == 1 {
-        trainer.copy_from_slice(&file[pointer..pointer + 512]);
-        pointer += 512;
+    if ines_header.trainer {
+        trainer = read_section(file, &mut pointer, 512, "trainer")?.to_vec();
     }
+    let prg_rom = read_section(file, &mut pointer, ines_header.prg_rom_size, "PRG-ROM")?.to_vec();
+    let chr_rom = read_section(file, &mut pointer, ines_header.chr_rom_size, "CHR-ROM")?.to_vec();
 
-    let mut prg_rom = vec![0; 16384 * ines_header.prg_rom_size as usize];
-    if ines_header.prg_rom_size > 0 {
-        prg_rom
-            .copy_from_slice(&file[pointer..pointer + 16384 * ines_header.prg_rom_size as usize]);
-        pointer += 16384 * ines_header.prg_rom_size as usize;
+    finish_loading(ines_header, trainer, prg_rom, chr_rom, None)
+}
+
+// Shared tail of every cartridge format: checksum, database fixups and mapper check
+fn finish_loading(
+    mut ines_header: iNES_header,
+    trainer: Vec<u8>,
+    prg_rom: Vec<u8>,
+    chr_rom: Vec<u8>,
+    board: Option<String>,
+) -> Result<iNES, RomError> {
+    let roms = [prg_rom.as_slice(), chr_rom.as_slice()].concat();
+    let crc32 = checksum::crc32(&roms);
+    let sha1 = checksum::sha1(&roms);
+    let database_entry = database::lookup(crc32, &sha1);
+    if let Some(entry) = &database_entry {
+        entry.apply(&mut ines_header);
+    }
+    // Carts without CHR-ROM always have at least 8 KiB of CHR-RAM
+    if chr_rom.is_empty() && ines_header.chr_ram_size + ines_header.chr_nvram_size == 0 {
+        ines_header.chr_ram_size = 0x2000;
     }
 
-    let mut chr_rom = vec![0; 8192 * ines_header.chr_rom_size as usize];
-    if ines_header.chr_rom_size > 0 {
-        chr_rom.copy_from_slice(&file[pointer..pointer + 8192 * ines_header.chr_rom_size as usize]);
-        pointer += 8192 * ines_header.chr_rom_size as usize;
+    if !is_supported(&ines_header) {
+        return Err(RomError::UnsupportedMapper(ines_header.mapper));
     }
 
-    let ines = iNES {
-        prg_rom: prg_rom,
-        chr_rom: chr_rom,
-        trainer: trainer,
+    Ok(iNES {
+        prg_rom,
+        chr_rom,
+        trainer,
         header: ines_header,
-    };
-
-    ines
+        crc32,
+        sha1,
+        database_name: database_entry.map(|entry| entry.name),
+        board,
+        disk_sides: vec![],
+        nsf: None,
+    })
 }
 
 pub fn compile_and_read_file(filename: &str) -> iNES {
     let file = std::fs::read(filename).unwrap();
 
-    let ines_header = iNES_header {
-        prg_rom_size: 1,
-        chr_rom_size: 0,
-    };
+    // Plain NROM-128 with CHR-RAM
+    let ines_header =
+        parse_header(&[b'N', b'E', b'S', 0x1A, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]).unwrap();
 
-    let mut prg_rom = vec![0; 16384 * ines_header.prg_rom_size as usize];
+    let mut prg_rom = vec![0; ines_header.prg_rom_size];
     prg_rom[0..file.len().min(16384)].copy_from_slice(&file[0..file.len().min(16384)]);
 
     prg_rom[16380] = 0x00;
     prg_rom[16381] = 0x80;
 
-    let ines = iNES {
-        prg_rom: prg_rom,
-        chr_rom: vec![],
-        trainer: vec![],
-        header: ines_header,
-    };
-
-    ines
+    finish_loading(ines_header, vec![], prg_rom, vec![], None).unwrap()
 }
@@ -0,0 +1,297 @@
+#[cfg(feature = "archives")]
+use std::io::Cursor;
+
+use crate::checksum;
+use crate::rom_reader::RomError;
+
+const ZIP_LOCAL_HEADER: &[u8] = b"PK\x03\x04";
+const ZIP_CENTRAL_HEADER: &[u8] = b"PK\x01\x02";
+const ZIP_END_OF_CENTRAL_DIRECTORY: &[u8] = b"PK\x05\x06";
+const GZIP_MAGIC: &[u8] = b"\x1F\x8B";
+const SEVEN_ZIP_MAGIC: &[u8] = b"7z\xBC\xAF\x27\x1C";
+
+const ROM_EXTENSIONS: [&str; 6] = ["nes", "fds", "nsf", "nsfe", "unf", "unif"];
+
+// Only the parts of an entry needed to find and decompress it
+struct Entry<'a> {
+    name: String,
+    method: u16,
+    crc32: u32,
+    size: usize,
+    data: &'a [u8],
+}
+
+pub fn is_archive(file: &[u8]) -> bool {
+    [ZIP_LOCAL_HEADER, GZIP_MAGIC, SEVEN_ZIP_MAGIC]
+        .iter()
+        .any(|magic| file.starts_with(magic))
+}
+
+fn is_rom_name(name: &str) -> bool {
+    name.rsplit_once('.').is_some_and(|(_, extension)| {
+        ROM_EXTENSIONS
+            .iter()
+            .any(|rom| extension.eq_ignore_ascii_case(rom))
+    })
+}
+
+fn bad_archive(reason: &str) -> RomError {
+    RomError::BadArchive(reason.to_string())
+}
+
+fn u16_at(file: &[u8], offset: usize) -> Result<u16, RomError> {
+    file.get(offset..offset + 2)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        .ok_or_else(|| bad_archive("truncated"))
+}
+
+fn u32_at(file: &[u8], offset: usize) -> Result<u32, RomError> {
+    file.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| bad_archive("truncated"))
+}
+
+// Entries are listed from the central directory, local headers can lack sizes when streamed
+fn zip_entries(file: &[u8]) -> Result<Vec<Entry<'_>>, RomError> {
+    // The end record is last, followed by a comment of at most 64K
+    let end = (0..file.len().saturating_sub(21))
+        .rev()
+        .take(0x10000 + 22)
+        .find(|&offset| file[offset..].starts_with(ZIP_END_OF_CENTRAL_DIRECTORY))
+        .ok_or_else(|| bad_archive("no zip end of central directory record"))?;
+    let entry_count = u16_at(file, end + 10)? as usize;
+    let mut pointer = u32_at(file, end + 16)? as usize;
+
+    let mut entries = Vec::new();
+    for _ in 0..entry_count {
+        if !file[pointer.min(file.len())..].starts_with(ZIP_CENTRAL_HEADER) {
+            return Err(bad_archive("broken zip central directory"));
+        }
+        let method = u16_at(file, pointer + 10)?;
+        let crc32 = u32_at(file, pointer + 16)?;
+        let compressed_size = u32_at(file, pointer + 20)? as usize;
+        let size = u32_at(file, pointer + 24)? as usize;
+        let name_length = u16_at(file, pointer + 28)? as usize;
+        let extra_length = u16_at(file, pointer + 30)? as usize;
+        let comment_length = u16_at(file, pointer + 32)? as usize;
+        let local_header = u32_at(file, pointer + 42)? as usize;
+        let name = file
+            .get(pointer + 46..pointer + 46 + name_length)
+            .ok_or_else(|| bad_archive("truncated"))?;
+        pointer += 46 + name_length + extra_length + comment_length;
+
+        if !file[local_header.min(file.len())..].starts_with(ZIP_LOCAL_HEADER) {
+            return Err(bad_archive("broken zip local header"));
+        }
+        let data_start = local_header
+            + 30
+            + u16_at(file, local_header + 26)? as usize
+            + u16_at(file, local_header + 28)? as usize;
+        let data = file
+            .get(data_start..data_start + compressed_size)
+            .ok_or_else(|| bad_archive("truncated"))?;
+
+        entries.push(Entry {
+            name: String::from_utf8_lossy(name).into_owned(),
+            method,
+            crc32,
+            size,
+            data,
+        });
+    }
+
+    Ok(entries)
+}
+
+fn gzip_entry(file: &[u8]) -> Result<Entry<'_>, RomError> {
+    const FEXTRA: u8 = 0b0000_0100;
+    const FNAME: u8 = 0b0000_1000;
+    const FCOMMENT: u8 = 0b0001_0000;
+    const FHCRC: u8 = 0b0000_0010;
+
+    let method = *file.get(2).ok_or_else(|| bad_archive("truncated"))?;
+    let flags = *file.get(3).ok_or_else(|| bad_archive("truncated"))?;
+    let mut pointer = 10;
+    if flags & FEXTRA != 0 {
+        pointer += 2 + u16_at(file, pointer)? as usize;
+    }
+    let mut name = String::new();
+    for (flag, keep) in [(FNAME, true), (FCOMMENT, false)] {
+        if flags & flag != 0 {
+            let field = file
+                .get(pointer..)
+                .ok_or_else(|| bad_archive("truncated"))?;
+            let length = field
+                .iter()
+                .position(|&b| b == 0)
+                .ok_or_else(|| bad_archive("truncated"))?;
+            if keep {
+                name = String::from_utf8_lossy(&field[..length]).into_owned();
+            }
+            pointer += length + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        pointer += 2;
+    }
+
+    let footer = file
+        .len()
+        .checked_sub(8)
+        .filter(|&footer| footer >= pointer)
+        .ok_or_else(|| bad_archive("truncated"))?;
+    Ok(Entry {
+        name,
+        // gzip's only method is deflate, 8, same number as zip
+        method: method as u16,
+        crc32: u32_at(file, footer)?,
+        size: u32_at(file, footer + 4)? as usize,
+        data: &file[pointer..footer],
+    })
+}
+
+// A .gz holds exactly one file, trust it's a ROM whatever its stored name.
+// 7z entries are listed by sevenz-rust instead
+fn rom_entries(file: &[u8]) -> Result<Vec<Entry<'_>>, RomError> {
+    if file.starts_with(GZIP_MAGIC) {
+        Ok(vec![gzip_entry(file)?])
+    } else {
+        Ok(zip_entries(file)?
+            .into_iter()
+            .filter(|entry| is_rom_name(&entry.name))
+            .collect())
+    }
+}
+
+fn decompress(entry: &Entry) -> Result<Vec<u8>, RomError> {
+    let data = match entry.method {
+        0 => entry.data.to_vec(),
+        8 => inflate(entry)?,
+        method => {
+            return Err(RomError::UnsupportedArchive(format!(
+                "compression method {method}"
+            )));
+        }
+    };
+    if data.len() != entry.size || checksum::crc32(&data) != entry.crc32 {
+        return Err(bad_archive(&format!("CRC mismatch in {}", entry.name)));
+    }
+
+    Ok(data)
+}
+
+// Decompression comes from miniz_oxide and sevenz-rust, builds without the archives feature
+// still recognize archives but refuse to open them
+#[cfg(not(feature = "archives"))]
+fn not_built() -> RomError {
+    RomError::UnsupportedArchive("built without the archives feature".to_string())
+}
+
+// The declared size caps the output, a lying header can't make it balloon
+#[cfg(feature = "archives")]
+fn inflate(entry: &Entry) -> Result<Vec<u8>, RomError> {
+    miniz_oxide::inflate::decompress_to_vec_with_limit(entry.data, entry.size)
+        .map_err(|_| bad_archive("corrupt deflate stream"))
+}
+
+#[cfg(not(feature = "archives"))]
+fn inflate(_entry: &Entry) -> Result<Vec<u8>, RomError> {
+    Err(not_built())
+}
+
+#[cfg(feature = "archives")]
+fn seven_zip_error(error: sevenz_rust::Error) -> RomError {
+    match error {
+        sevenz_rust::Error::UnsupportedCompressionMethod(method) => {
+            RomError::UnsupportedArchive(format!("7z method {method}"))
+        }
+        sevenz_rust::Error::Unsupported(what) => RomError::UnsupportedArchive(what.into_owned()),
+        sevenz_rust::Error::PasswordRequired => {
+            RomError::UnsupportedArchive("encrypted 7z".to_string())
+        }
+        error => bad_archive(&error.to_string()),
+    }
+}
+
+#[cfg(feature = "archives")]
+fn seven_zip_roms(file: &[u8]) -> Result<Vec<String>, RomError> {
+    let archive = sevenz_rust::Archive::read(&mut Cursor::new(file), file.len() as u64, &[])
+        .map_err(seven_zip_error)?;
+
+    Ok(archive
+        .files
+        .into_iter()
+        .filter(|entry| entry.has_stream && !entry.is_directory && is_rom_name(&entry.name))
+        .map(|entry| entry.name)
+        .collect())
+}
+
+#[cfg(not(feature = "archives"))]
+fn seven_zip_roms(_file: &[u8]) -> Result<Vec<String>, RomError> {
+    Err(not_built())
+}
+
+// Solid blocks only decode front to back, entries before the wanted one are read and dropped
+#[cfg(feature = "archives")]
+fn seven_zip_extract(file: &[u8], name: &str) -> Result<Vec<u8>, RomError> {
+    let mut reader = sevenz_rust::SevenZReader::new(
+        Cursor::new(file),
+        file.len() as u64,
+        sevenz_rust::Password::empty(),
+    )
+    .map_err(seven_zip_error)?;
+    let mut rom = Vec::new();
+    reader
+        .for_each_entries(|entry, data| {
+            if entry.name == name {
+                data.read_to_end(&mut rom)?;
+                return Ok(false);
+            }
+            std::io::copy(data, &mut std::io::sink())?;
+            Ok(true)
+        })
+        .map_err(seven_zip_error)?;
+
+    Ok(rom)
+}
+
+#[cfg(not(feature = "archives"))]
+fn seven_zip_extract(_file: &[u8], _name: &str) -> Result<Vec<u8>, RomError> {
+    Err(not_built())
+}
+
+pub fn entry_names(file: &[u8]) -> Result<Vec<String>, RomError> {
+    if file.starts_with(SEVEN_ZIP_MAGIC) {
+        return seven_zip_roms(file);
+    }
+    Ok(rom_entries(file)?
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect())
+}
+
+// Without a name the first ROM in archive order wins
+pub fn extract(file: &[u8], entry_name: Option<&str>) -> Result<Vec<u8>, RomError> {
+    if file.starts_with(SEVEN_ZIP_MAGIC) {
+        let entries = seven_zip_roms(file)?;
+        let name = match entry_name {
+            Some(entry_name) => entries
+                .iter()
+                .find(|name| *name == entry_name)
+                .ok_or_else(|| RomError::MissingEntry(entry_name.to_string()))?,
+            None => entries.first().ok_or(RomError::NoRomInArchive)?,
+        };
+        return seven_zip_extract(file, name);
+    }
+
+    let entries = rom_entries(file)?;
+    let entry = match entry_name {
+        Some(entry_name) => entries
+            .iter()
+            .find(|entry| entry.name == entry_name)
+            .ok_or_else(|| RomError::MissingEntry(entry_name.to_string()))?,
+        None => entries.first().ok_or(RomError::NoRomInArchive)?,
+    };
+
+    decompress(entry)
+}
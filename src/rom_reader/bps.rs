@@ -0,0 +1,108 @@
+use crate::checksum;
+use crate::rom_reader::RomError;
+
+fn bad_patch() -> RomError {
+    RomError::BadPatch("BPS".to_string())
+}
+
+// Variable-length number shared by BPS and UPS, 7 bits per byte with the top bit ending it
+pub fn decode_number(patch: &[u8], pointer: &mut usize) -> Option<usize> {
+    let mut number = 0usize;
+    let mut shift = 1usize;
+    loop {
+        let byte = *patch.get(*pointer)?;
+        *pointer += 1;
+        number = number.checked_add((byte & 0x7F) as usize * shift)?;
+        if byte & 0x80 != 0 {
+            return Some(number);
+        }
+        shift = shift.checked_mul(128)?;
+        number = number.checked_add(shift)?;
+    }
+}
+
+// Footer holds CRC32s of the source, the target and the patch itself
+pub fn read_footer(patch: &[u8]) -> Option<[u32; 3]> {
+    let footer = patch.get(patch.len().checked_sub(12)?..)?;
+    Some([0, 4, 8].map(|offset| u32::from_le_bytes(footer[offset..offset + 4].try_into().unwrap())))
+}
+
+pub fn apply(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, RomError> {
+    if !patch.starts_with(b"BPS1") {
+        return Err(bad_patch());
+    }
+    let [source_crc, target_crc, patch_crc] = read_footer(patch).ok_or_else(bad_patch)?;
+    if checksum::crc32(&patch[..patch.len() - 4]) != patch_crc {
+        return Err(bad_patch());
+    }
+    let found = checksum::crc32(source);
+    if found != source_crc {
+        return Err(RomError::PatchMismatch {
+            format: "BPS",
+            expected: source_crc,
+            found,
+        });
+    }
+
+    let actions_end = patch.len() - 12;
+    let mut pointer = 4;
+    let _source_size = decode_number(patch, &mut pointer).ok_or_else(bad_patch)?;
+    let target_size = decode_number(patch, &mut pointer).ok_or_else(bad_patch)?;
+    let metadata_size = decode_number(patch, &mut pointer).ok_or_else(bad_patch)?;
+    pointer = pointer
+        .checked_add(metadata_size)
+        .filter(|&end| end <= actions_end)
+        .ok_or_else(bad_patch)?;
+
+    // Sizes come from the patch, only trust them as far as the data could actually go
+    let mut target = Vec::with_capacity(target_size.min(source.len() + patch.len()));
+    let mut source_offset = 0isize;
+    let mut target_offset = 0isize;
+    while pointer < actions_end {
+        let command = decode_number(patch, &mut pointer).ok_or_else(bad_patch)?;
+        let length = (command >> 2) + 1;
+        if length > target_size - target.len() {
+            return Err(bad_patch());
+        }
+        match command & 0b11 {
+            // SourceRead
+            0 => {
+                let start = target.len();
+                target.extend_from_slice(source.get(start..start + length).ok_or_else(bad_patch)?);
+            }
+            // TargetRead
+            1 => {
+                target
+                    .extend_from_slice(patch.get(pointer..pointer + length).ok_or_else(bad_patch)?);
+                pointer += length;
+            }
+            // SourceCopy
+            2 => {
+                let offset = decode_number(patch, &mut pointer).ok_or_else(bad_patch)?;
+                source_offset += if offset & 1 != 0 { -1 } else { 1 } * (offset >> 1) as isize;
+                let start = usize::try_from(source_offset).map_err(|_| bad_patch())?;
+                target.extend_from_slice(source.get(start..start + length).ok_or_else(bad_patch)?);
+                source_offset += length as isize;
+            }
+            // TargetCopy, byte by byte since the ranges may overlap
+            _ => {
+                let offset = decode_number(patch, &mut pointer).ok_or_else(bad_patch)?;
+                target_offset += if offset & 1 != 0 { -1 } else { 1 } * (offset >> 1) as isize;
+                for _ in 0..length {
+                    let byte = usize::try_from(target_offset)
+                        .ok()
+                        .and_then(|offset| target.get(offset).copied())
+                        .ok_or_else(bad_patch)?;
+                    target.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    if target.len() != target_size || checksum::crc32(&target) != target_crc {
+        return Err(bad_patch());
+    }
+
+    Ok(target)
+}
@@ -0,0 +1,162 @@
+use crate::rom_reader::{Mirroring, Timing, iNES_header};
+
+// Embedded at build time, regenerate it with examples/import_nes20db.rs
+const DATABASE: &str = include_str!("../../assets/database.txt");
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatabaseEntry {
+    pub crc32: u32,
+    pub sha1: Option<[u8; 20]>,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub mirroring: Mirroring,
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub timing: Timing,
+    pub name: String,
+}
+
+impl DatabaseEntry {
+    // Dumps with a matching checksum are known good, the header is what's wrong
+    pub fn apply(&self, header: &mut iNES_header) {
+        header.mapper = self.mapper;
+        header.submapper = self.submapper;
+        header.mirroring = self.mirroring;
+        header.battery = self.prg_nvram_size > 0;
+        header.prg_ram_size = self.prg_ram_size;
+        header.prg_nvram_size = self.prg_nvram_size;
+        header.chr_ram_size = self.chr_ram_size;
+        header.timing = self.timing;
+    }
+}
+
+fn parse_sha1(text: &str) -> Option<[u8; 20]> {
+    if text.len() != 40 {
+        return None;
+    }
+    let mut sha1 = [0; 20];
+    for (i, byte) in sha1.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).ok()?;
+    }
+
+    Some(sha1)
+}
+
+fn parse_entry(line: &str) -> Option<DatabaseEntry> {
+    let mut fields = line.split_whitespace();
+    let crc32 = u32::from_str_radix(fields.next()?, 16).ok()?;
+    let sha1 = match fields.next()? {
+        "-" => None,
+        text => Some(parse_sha1(text)?),
+    };
+    let mapper = fields.next()?.parse().ok()?;
+    let submapper = fields.next()?.parse().ok()?;
+    let mirroring = match fields.next()? {
+        "H" => Mirroring::Horizontal,
+        "V" => Mirroring::Vertical,
+        "4" => Mirroring::FourScreen,
+        _ => return None,
+    };
+    let prg_ram_size = fields.next()?.parse().ok()?;
+    let prg_nvram_size = fields.next()?.parse().ok()?;
+    let chr_ram_size = fields.next()?.parse().ok()?;
+    let timing = match fields.next()? {
+        "NTSC" => Timing::Ntsc,
+        "PAL" => Timing::Pal,
+        "MULTI" => Timing::MultiRegion,
+        "DENDY" => Timing::Dendy,
+        _ => return None,
+    };
+    let name = fields.collect::<Vec<_>>().join(" ");
+
+    Some(DatabaseEntry {
+        crc32,
+        sha1,
+        mapper,
+        submapper,
+        mirroring,
+        prg_ram_size,
+        prg_nvram_size,
+        chr_ram_size,
+        timing,
+        name,
+    })
+}
+
+pub fn find_entry(database: &str, crc32: u32, sha1: &[u8; 20]) -> Option<DatabaseEntry> {
+    database
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(parse_entry)
+        .find(|entry| {
+            entry.crc32 == crc32 && entry.sha1.is_none_or(|entry_sha1| entry_sha1 == *sha1)
+        })
+}
+
+pub fn lookup(crc32: u32, sha1: &[u8; 20]) -> Option<DatabaseEntry> {
+    find_entry(DATABASE, crc32, sha1)
+}
+
+// The value of name="..." on the first <element .../> tag in text
+fn attribute<'a>(text: &'a str, element: &str, name: &str) -> Option<&'a str> {
+    let start = text.find(&format!("<{element} "))?;
+    let tag = &text[start..start + text[start..].find('>')?];
+    let key = format!(" {name}=\"");
+    let value_start = tag.find(&key)? + key.len();
+    let value_end = value_start + tag[value_start..].find('"')?;
+
+    Some(&tag[value_start..value_end])
+}
+
+// One <game> element as a database line. Mapper-controlled and one-screen mirroring have no
+// column of their own and fall back to the header's default, horizontal
+fn nes20db_line(game: &str, name: &str) -> Option<String> {
+    let crc32 = attribute(game, "rom", "crc32")?;
+    let sha1 = attribute(game, "rom", "sha1").map_or("-".to_string(), str::to_lowercase);
+    let mapper = attribute(game, "pcb", "mapper")?;
+    let submapper = attribute(game, "pcb", "submapper").unwrap_or("0");
+    let mirroring = match attribute(game, "pcb", "mirroring") {
+        Some("V") => "V",
+        Some("4") => "4",
+        _ => "H",
+    };
+    let size = |element| attribute(game, element, "size").unwrap_or("0");
+    let timing = match attribute(game, "console", "region") {
+        Some("1") => "PAL",
+        Some("2") => "MULTI",
+        Some("3") => "DENDY",
+        _ => "NTSC",
+    };
+    let line = format!(
+        "{crc32} {sha1} {mapper} {submapper} {mirroring} {} {} {} {timing} {name}",
+        size("prgram"),
+        size("prgnvram"),
+        size("chrram")
+    );
+
+    parse_entry(&line).map(|_| line)
+}
+
+// Converts the NES 2.0 XML database (nes20db.xml) into database.txt lines. Each <game> is
+// named by the comment before it, which holds the dump's file path
+pub fn from_nes20db(xml: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find("<game>") {
+        let name = rest[..start]
+            .rfind("<!--")
+            .and_then(|comment| rest[comment + 4..start].split("-->").next())
+            .map(|path| path.trim().rsplit(['\\', '/']).next().unwrap_or_default())
+            .map(|file| file.strip_suffix(".nes").unwrap_or(file))
+            .unwrap_or_default();
+        let end = rest[start..]
+            .find("</game>")
+            .map_or(rest.len(), |end| start + end);
+        lines.extend(nes20db_line(&rest[start..end], name));
+        rest = &rest[end..];
+    }
+
+    lines
+}
@@ -0,0 +1,120 @@
+use std::path::Path;
+
+use crate::checksum;
+use crate::rom_reader::{
+    ConsoleType, HeaderFormat, Mirroring, RomError, Timing, finish_loading, iNES, iNES_header,
+    read_bytes,
+};
+
+// fwNES images store each side as 65500 bytes of blocks without CRCs or gaps
+pub const SIDE_SIZE: usize = 65500;
+// Quick Disk dumps are 64 KiB per side and keep the 2 CRC bytes after every block
+const QD_SIDE_SIZE: usize = 0x10000;
+
+pub const BIOS_SIZE: usize = 0x2000;
+
+pub fn is_disk_image(file: &[u8]) -> bool {
+    file.starts_with(b"FDS\x1A") || file.starts_with(b"\x01*NINTENDO-HVC*")
+}
+
+// Block 1 is the disk info, 2 the file count, 3 a file header and 4 the file data,
+// whose size comes from the preceding file header
+pub fn block_length(block_type: u8, file_size: usize) -> Option<usize> {
+    match block_type {
+        1 => Some(56),
+        2 => Some(2),
+        3 => Some(16),
+        4 => Some(1 + file_size),
+        _ => None,
+    }
+}
+
+fn strip_crcs(qd_side: &[u8]) -> Vec<u8> {
+    let mut side = Vec::with_capacity(SIDE_SIZE);
+    let mut pointer = 0;
+    let mut file_size = 0;
+    while let Some(&block_type) = qd_side.get(pointer) {
+        let Some(length) = block_length(block_type, file_size) else {
+            break;
+        };
+        let Some(block) = qd_side.get(pointer..pointer + length) else {
+            break;
+        };
+        if block_type == 3 {
+            file_size = u16::from_le_bytes([block[13], block[14]]) as usize;
+        }
+        side.extend_from_slice(block);
+        pointer += length + 2;
+    }
+
+    side
+}
+
+pub fn parse_fds(file: &[u8]) -> Result<iNES, RomError> {
+    let (data, side_size) = if file.starts_with(b"FDS\x1A") {
+        (file.get(16..).unwrap_or_default(), SIDE_SIZE)
+    } else if file.len().is_multiple_of(QD_SIDE_SIZE) && !file.len().is_multiple_of(SIDE_SIZE) {
+        (file, QD_SIDE_SIZE)
+    } else {
+        (file, SIDE_SIZE)
+    };
+    if data.is_empty() || !data.len().is_multiple_of(side_size) {
+        return Err(RomError::Truncated {
+            section: "disk side",
+            expected: side_size,
+            found: data.len() % side_size,
+        });
+    }
+
+    let disk_sides: Vec<Vec<u8>> = data
+        .chunks(side_size)
+        .map(|side| {
+            let mut side = if side_size == QD_SIDE_SIZE {
+                strip_crcs(side)
+            } else {
+                side.to_vec()
+            };
+            side.resize(SIDE_SIZE, 0);
+            side
+        })
+        .collect();
+
+    // The RAM adapter is mapper 20, the BIOS takes the place of PRG-ROM
+    let ines_header = iNES_header {
+        format: HeaderFormat::Fds,
+        prg_rom_size: 0,
+        chr_rom_size: 0,
+        mapper: 20,
+        submapper: 0,
+        mirroring: Mirroring::Horizontal,
+        battery: false,
+        trainer: false,
+        prg_ram_size: 0x8000,
+        prg_nvram_size: 0,
+        chr_ram_size: 0x2000,
+        chr_nvram_size: 0,
+        timing: Timing::Ntsc,
+        console_type: ConsoleType::Nes,
+        misc_roms: 0,
+        expansion_device: 0x01,
+    };
+
+    let mut ines = finish_loading(ines_header, vec![], vec![], vec![], None)?;
+    let disk = disk_sides.concat();
+    ines.crc32 = checksum::crc32(&disk);
+    ines.sha1 = checksum::sha1(&disk);
+    ines.disk_sides = disk_sides;
+
+    Ok(ines)
+}
+
+pub fn load_bios(ines: &mut iNES, filename: &str) -> Result<(), RomError> {
+    let bios = read_bytes(Path::new(filename))?;
+    if bios.len() != BIOS_SIZE {
+        return Err(RomError::BadBios(bios.len()));
+    }
+    ines.header.prg_rom_size = BIOS_SIZE;
+    ines.prg_rom = bios;
+
+    Ok(())
+}
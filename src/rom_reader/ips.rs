@@ -0,0 +1,83 @@
+use crate::rom_reader::RomError;
+
+const EOF_MARKER: &[u8; 3] = b"EOF";
+
+pub fn apply(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, RomError> {
+    let bad_patch = || RomError::BadPatch("IPS".to_string());
+    if !patch.starts_with(b"PATCH") {
+        return Err(bad_patch());
+    }
+
+    let mut output = source.to_vec();
+    let mut pointer = 5;
+    loop {
+        let record = patch.get(pointer..pointer + 3).ok_or_else(bad_patch)?;
+        if record == EOF_MARKER {
+            pointer += 3;
+            break;
+        }
+        let offset = u32::from_be_bytes([0, record[0], record[1], record[2]]) as usize;
+        let size = u16::from_be_bytes(
+            patch
+                .get(pointer + 3..pointer + 5)
+                .ok_or_else(bad_patch)?
+                .try_into()
+                .unwrap(),
+        ) as usize;
+        pointer += 5;
+
+        let data = if size == 0 {
+            // RLE record: 16-bit run length and a single fill byte
+            let rle = patch.get(pointer..pointer + 3).ok_or_else(bad_patch)?;
+            pointer += 3;
+            vec![rle[2]; u16::from_be_bytes([rle[0], rle[1]]) as usize]
+        } else {
+            let data = patch.get(pointer..pointer + size).ok_or_else(bad_patch)?;
+            pointer += size;
+            data.to_vec()
+        };
+
+        if output.len() < offset + data.len() {
+            output.resize(offset + data.len(), 0);
+        }
+        output[offset..offset + data.len()].copy_from_slice(&data);
+    }
+
+    // Optional truncation extension
+    if let Some(length) = patch.get(pointer..pointer + 3) {
+        output.truncate(u32::from_be_bytes([0, length[0], length[1], length[2]]) as usize);
+    }
+
+    Ok(output)
+}
+
+// Both buffers are expected to be the same length, IPS offsets are limited to 16 MiB
+pub fn create(source: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut patch = b"PATCH".to_vec();
+
+    let mut offset = 0;
+    while offset < target.len() {
+        if source.get(offset) == Some(&target[offset]) {
+            offset += 1;
+            continue;
+        }
+
+        let mut start = offset;
+        // A record at $454F46 would read as the EOF marker, start one byte earlier
+        if start as u32 == u32::from_be_bytes([0, b'E', b'O', b'F']) {
+            start -= 1;
+        }
+        let mut end = offset;
+        while end < target.len() && end - start < 0xFFFF && source.get(end) != Some(&target[end]) {
+            end += 1;
+        }
+
+        patch.extend_from_slice(&(start as u32).to_be_bytes()[1..4]);
+        patch.extend_from_slice(&((end - start) as u16).to_be_bytes());
+        patch.extend_from_slice(&target[start..end]);
+        offset = end;
+    }
+
+    patch.extend_from_slice(EOF_MARKER);
+    patch
+}
@@ -0,0 +1,262 @@
+use crate::rom_reader::{
+    ConsoleType, HeaderFormat, Mirroring, RomError, Timing, finish_loading, iNES, iNES_header,
+};
+
+// Play rates in microseconds when the file doesn't say otherwise
+const DEFAULT_NTSC_SPEED: u16 = 16639;
+const DEFAULT_PAL_SPEED: u16 = 19997;
+
+#[derive(Debug, Clone, Default)]
+pub struct NsfInfo {
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+    pub songs: u8,
+    // 0-based, NSF stores it 1-based
+    pub starting_song: u8,
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    pub ntsc_speed: u16,
+    pub pal_speed: u16,
+    // None when the tune doesn't bankswitch and is simply loaded at load_address
+    pub bankswitch: Option<[u8; 8]>,
+    pub pal: bool,
+    // VRC6, VRC7, FDS, MMC5, Namco 163, Sunsoft 5B, VT02+ from bit 0 up
+    pub expansion_chips: u8,
+    // NSFe only
+    pub track_labels: Vec<String>,
+    pub track_times: Vec<Option<u32>>,
+}
+
+impl NsfInfo {
+    pub fn expansion_chip_names(&self) -> Vec<&'static str> {
+        [
+            "VRC6",
+            "VRC7",
+            "FDS",
+            "MMC5",
+            "Namco 163",
+            "Sunsoft 5B",
+            "VT02+",
+        ]
+        .into_iter()
+        .enumerate()
+        .filter(|(bit, _)| self.expansion_chips & (1 << bit) != 0)
+        .map(|(_, name)| name)
+        .collect()
+    }
+}
+
+fn string(data: &[u8]) -> String {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).trim().to_string()
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+pub fn is_nsf(file: &[u8]) -> bool {
+    file.starts_with(b"NESM\x1A") || file.starts_with(b"NSFE")
+}
+
+fn parse_nsf_header(file: &[u8]) -> Result<(NsfInfo, &[u8]), RomError> {
+    if file.len() < 0x80 {
+        return Err(RomError::Truncated {
+            section: "NSF header",
+            expected: 0x80,
+            found: file.len(),
+        });
+    }
+
+    let banks: [u8; 8] = file[0x70..0x78].try_into().unwrap();
+    let info = NsfInfo {
+        load_address: u16_at(file, 0x08),
+        init_address: u16_at(file, 0x0A),
+        play_address: u16_at(file, 0x0C),
+        songs: file[0x06],
+        starting_song: file[0x07].saturating_sub(1),
+        title: string(&file[0x0E..0x2E]),
+        artist: string(&file[0x2E..0x4E]),
+        copyright: string(&file[0x4E..0x6E]),
+        ntsc_speed: u16_at(file, 0x6E),
+        pal_speed: u16_at(file, 0x78),
+        bankswitch: banks.iter().any(|&bank| bank != 0).then_some(banks),
+        // Bit 0 is PAL, bit 1 dual region which plays fine as NTSC
+        pal: file[0x7A] & 0b0000_0011 == 0b0000_0001,
+        expansion_chips: file[0x7B],
+        track_labels: vec![],
+        track_times: vec![],
+    };
+
+    // NSF2 stores the program length, 0 means until the end of the file
+    let length = u32::from_le_bytes([file[0x7D], file[0x7E], file[0x7F], 0]) as usize;
+    let data = &file[0x80..];
+    let data = if length != 0 && length <= data.len() {
+        &data[..length]
+    } else {
+        data
+    };
+
+    Ok((info, data))
+}
+
+fn parse_nsfe(file: &[u8]) -> Result<(NsfInfo, Vec<u8>), RomError> {
+    let mut info = NsfInfo {
+        ntsc_speed: DEFAULT_NTSC_SPEED,
+        pal_speed: DEFAULT_PAL_SPEED,
+        ..Default::default()
+    };
+    let mut data = None;
+    let mut has_info = false;
+
+    let mut pointer = 4;
+    while pointer + 8 <= file.len() {
+        let length = u32::from_le_bytes(file[pointer..pointer + 4].try_into().unwrap()) as usize;
+        let id = &file[pointer + 4..pointer + 8];
+        pointer += 8;
+        let Some(chunk) = file.get(pointer..pointer + length) else {
+            return Err(RomError::Truncated {
+                section: "NSFe chunk",
+                expected: length,
+                found: file.len() - pointer,
+            });
+        };
+        pointer += length;
+
+        match id {
+            b"INFO" => {
+                if chunk.len() < 8 {
+                    return Err(RomError::BadNsf("INFO chunk too short".to_string()));
+                }
+                has_info = true;
+                info.load_address = u16_at(chunk, 0);
+                info.init_address = u16_at(chunk, 2);
+                info.play_address = u16_at(chunk, 4);
+                info.pal = chunk[6] & 0b0000_0011 == 0b0000_0001;
+                info.expansion_chips = chunk[7];
+                info.songs = chunk.get(8).copied().unwrap_or(1);
+                info.starting_song = chunk.get(9).copied().unwrap_or(0);
+            }
+            b"DATA" => data = Some(chunk.to_vec()),
+            b"BANK" => {
+                let mut banks = [0; 8];
+                let len = chunk.len().min(8);
+                banks[..len].copy_from_slice(&chunk[..len]);
+                info.bankswitch = Some(banks);
+            }
+            b"RATE" => {
+                if chunk.len() >= 2 {
+                    info.ntsc_speed = u16_at(chunk, 0);
+                }
+                if chunk.len() >= 4 {
+                    info.pal_speed = u16_at(chunk, 2);
+                }
+            }
+            b"auth" => {
+                let mut strings = chunk.split(|&b| b == 0).map(string);
+                info.title = strings.next().unwrap_or_default();
+                info.artist = strings.next().unwrap_or_default();
+                info.copyright = strings.next().unwrap_or_default();
+            }
+            b"tlbl" => {
+                info.track_labels = chunk.split(|&b| b == 0).map(string).collect();
+                info.track_labels.truncate(info.songs as usize);
+            }
+            b"time" => {
+                info.track_times = chunk
+                    .chunks_exact(4)
+                    .map(|time| {
+                        let ms = i32::from_le_bytes(time.try_into().unwrap());
+                        (ms >= 0).then_some(ms as u32)
+                    })
+                    .collect();
+            }
+            b"NEND" => break,
+            // An unknown chunk starting with an uppercase letter is required for playback
+            _ if id[0].is_ascii_uppercase() => {
+                return Err(RomError::BadNsf(format!(
+                    "unsupported required chunk {}",
+                    String::from_utf8_lossy(id)
+                )));
+            }
+            _ => {}
+        }
+    }
+
+    if !has_info {
+        return Err(RomError::BadNsf("missing INFO chunk".to_string()));
+    }
+    let data = data.ok_or(RomError::BadNsf("missing DATA chunk".to_string()))?;
+
+    Ok((info, data))
+}
+
+// Lays the program out in 4 KiB banks so $5FF8-$5FFF can map it, non-bankswitched
+// tunes get an identity mapping over a 32 KiB image
+fn build_prg_rom(info: &mut NsfInfo, data: &[u8]) -> Vec<u8> {
+    let mut prg_rom = match info.bankswitch {
+        Some(_) => {
+            let mut prg_rom = vec![0; (info.load_address & 0x0FFF) as usize];
+            prg_rom.extend_from_slice(data);
+            prg_rom
+        }
+        None => {
+            let mut prg_rom = vec![0; 0x8000];
+            let offset = (info.load_address - 0x8000) as usize;
+            let len = data.len().min(0x8000 - offset);
+            prg_rom[offset..offset + len].copy_from_slice(&data[..len]);
+            info.bankswitch = Some([0, 1, 2, 3, 4, 5, 6, 7]);
+            prg_rom
+        }
+    };
+    prg_rom.resize(prg_rom.len().next_multiple_of(0x1000), 0);
+
+    prg_rom
+}
+
+pub fn parse_nsf(file: &[u8]) -> Result<iNES, RomError> {
+    let (mut info, data) = if file.starts_with(b"NSFE") {
+        parse_nsfe(file)?
+    } else {
+        let (info, data) = parse_nsf_header(file)?;
+        (info, data.to_vec())
+    };
+    if info.songs == 0 {
+        return Err(RomError::BadNsf("no songs".to_string()));
+    }
+    // $6000-$7FFF is the player's PRG-RAM, the program only ever lives in $8000-$FFFF
+    if info.load_address < 0x8000 {
+        return Err(RomError::BadNsf(format!(
+            "load address ${:04X} is below $8000",
+            info.load_address
+        )));
+    }
+    let prg_rom = build_prg_rom(&mut info, &data);
+
+    // Mapper 31 is the cartridge version of NSF bankswitching
+    let ines_header = iNES_header {
+        format: HeaderFormat::Nsf,
+        prg_rom_size: prg_rom.len(),
+        chr_rom_size: 0,
+        mapper: 31,
+        submapper: 0,
+        mirroring: Mirroring::Horizontal,
+        battery: false,
+        trainer: false,
+        prg_ram_size: 0x2000,
+        prg_nvram_size: 0,
+        chr_ram_size: 0x2000,
+        chr_nvram_size: 0,
+        timing: if info.pal { Timing::Pal } else { Timing::Ntsc },
+        console_type: ConsoleType::Nes,
+        misc_roms: 0,
+        expansion_device: 0,
+    };
+
+    let mut ines = finish_loading(ines_header, vec![], prg_rom, vec![], None)?;
+    ines.nsf = Some(info);
+
+    Ok(ines)
+}
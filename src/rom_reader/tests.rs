@@ -0,0 +1,641 @@
+use crate::checksum;
+use crate::rom_reader::{
+    ConsoleType, HeaderFormat, Mirroring, RomError, Timing, apply_patch, database, fds, ips,
+    parse_header, parse_ines, parse_rom, read_file,
+};
+#[cfg(feature = "archives")]
+use crate::rom_reader::{archive_entries, load_file, read_file_with};
+
+#[test]
+fn header_ines() {
+    let header = parse_header(b"NES\x1A\x02\x01\x13\x40\0\0\0\0\0\0\0\0").unwrap();
+
+    assert_eq!(header.format, HeaderFormat::INes);
+    assert_eq!(header.prg_rom_size, 0x8000);
+    assert_eq!(header.chr_rom_size, 0x2000);
+    assert_eq!(header.mapper, 0x41);
+    assert_eq!(header.mirroring, Mirroring::Vertical);
+    assert!(header.battery);
+    assert_eq!(header.prg_ram_size, 0);
+    assert_eq!(header.prg_nvram_size, 0x2000);
+    assert_eq!(header.chr_ram_size, 0);
+    assert_eq!(header.timing, Timing::Ntsc);
+}
+
+#[test]
+fn header_diskdude() {
+    let header = parse_header(b"NES\x1A\x01\x00\x20DiskDude!").unwrap();
+
+    assert_eq!(header.format, HeaderFormat::Archaic);
+    assert_eq!(header.mapper, 2);
+    assert_eq!(header.console_type, ConsoleType::Nes);
+}
+
+#[test]
+fn header_nes20() {
+    let header = parse_header(b"NES\x1A\x10\x00\x48\x1B\x31\x00\x70\x07\x03\x01\x00\x01").unwrap();
+
+    assert_eq!(header.format, HeaderFormat::Nes20);
+    assert_eq!(header.prg_rom_size, 0x40000);
+    assert_eq!(header.chr_rom_size, 0);
+    assert_eq!(header.mapper, 0x114);
+    assert_eq!(header.submapper, 3);
+    assert_eq!(header.mirroring, Mirroring::FourScreen);
+    assert_eq!(header.prg_ram_size, 0);
+    assert_eq!(header.prg_nvram_size, 0x2000);
+    assert_eq!(header.chr_ram_size, 0x2000);
+    assert_eq!(header.timing, Timing::Dendy);
+    assert_eq!(header.console_type, ConsoleType::Extended(1));
+    assert_eq!(header.expansion_device, 1);
+}
+
+#[test]
+fn header_nes20_exponent_size() {
+    // 2^5 * 3 = 96 bytes of PRG-ROM
+    let header = parse_header(b"NES\x1A\x15\x00\x00\x08\x00\x0F\0\0\0\0\0\0").unwrap();
+
+    assert_eq!(header.prg_rom_size, 96);
+
+    // 2^63 * 7 overflows, which is a bad header rather than a crash
+    assert!(matches!(
+        parse_header(b"NES\x1A\xFF\x00\x00\x08\x00\x0F\0\0\0\0\0\0"),
+        Err(RomError::BadHeader(_))
+    ));
+    assert!(matches!(
+        parse_ines(b"NES\x1A\x00\xFF\x00\x08\x00\xF0\0\0\0\0\0\0"),
+        Err(RomError::BadHeader(_))
+    ));
+}
+
+#[test]
+fn ines_errors() {
+    assert!(matches!(parse_ines(b"NES"), Err(RomError::BadMagic)));
+    assert!(matches!(
+        parse_ines(b"UNIF\0\0\0\0\0\0\0\0\0\0\0\0"),
+        Err(RomError::BadMagic)
+    ));
+    assert!(matches!(
+        parse_ines(b"NES\x1A\x01\x01\0\0\0\0\0\0\0\0\0\0"),
+        Err(RomError::Truncated {
+            section: "PRG-ROM",
+            expected: 0x4000,
+            found: 0
+        })
+    ));
+    let mut mmc3 = b"NES\x1A\x01\x00\x40\0\0\0\0\0\0\0\0\0".to_vec();
+    mmc3.extend([0; 0x4000]);
+    assert!(matches!(
+        parse_ines(&mmc3),
+        Err(RomError::UnsupportedMapper(4))
+    ));
+    // Mapper 20 needs the disk and BIOS of an FDS image
+    let mut ram_adapter = b"NES\x1A\x01\x00\x40\x10\0\0\0\0\0\0\0\0".to_vec();
+    ram_adapter.extend([0; 0x4000]);
+    assert!(matches!(
+        parse_ines(&ram_adapter),
+        Err(RomError::UnsupportedMapper(20))
+    ));
+    // Mapper 31 carts bankswitch like NSFs, which only the player does
+    let mut mapper_31 = b"NES\x1A\x02\x00\xF0\x10\0\0\0\0\0\0\0\0".to_vec();
+    mapper_31.extend([0; 0x8000]);
+    assert!(matches!(
+        parse_ines(&mapper_31),
+        Err(RomError::UnsupportedMapper(31))
+    ));
+    assert!(matches!(
+        read_file("./assets/tests/missing.nes"),
+        Err(RomError::MissingFile(_))
+    ));
+}
+
+#[test]
+fn ines_trainer() {
+    let mut file = b"NES\x1A\x01\x00\x04\0\0\0\0\0\0\0\0\0".to_vec();
+    file.extend([0xAA; 512]);
+    file.extend([0x55; 0x4000]);
+    let ines = parse_ines(&file).unwrap();
+
+    assert_eq!(ines.trainer, [0xAA; 512]);
+    assert_eq!(ines.prg_rom, [0x55; 0x4000]);
+    assert!(ines.chr_rom.is_empty());
+    // No CHR-ROM means 8 KiB of CHR-RAM
+    assert_eq!(ines.header.chr_ram_size, 0x2000);
+}
+
+#[test]
+fn checksums() {
+    assert_eq!(checksum::crc32(b"123456789"), 0xCBF43926);
+    assert_eq!(
+        checksum::sha1(b"abc"),
+        [
+            0xA9, 0x99, 0x3E, 0x36, 0x47, 0x06, 0x81, 0x6A, 0xBA, 0x3E, 0x25, 0x71, 0x78, 0x50,
+            0xC2, 0x6C, 0x9C, 0xD0, 0xD8, 0x9D
+        ]
+    );
+}
+
+#[test]
+fn database_override() {
+    let database = "
+        # crc32 sha1 mapper sub mirroring prg_ram prg_nvram chr_ram timing name
+        1234ABCD - 1 2 V 0 8192 0 PAL Some Game (Europe)
+    ";
+    let entry = database::find_entry(database, 0x1234ABCD, &[0; 20]).unwrap();
+    assert_eq!(entry.name, "Some Game (Europe)");
+    assert!(database::find_entry(database, 0x1234ABCE, &[0; 20]).is_none());
+
+    let mut header = parse_header(b"NES\x1A\x01\x01\0\0\0\0\0\0\0\0\0\0").unwrap();
+    entry.apply(&mut header);
+    assert_eq!(header.mapper, 1);
+    assert_eq!(header.submapper, 2);
+    assert_eq!(header.mirroring, Mirroring::Vertical);
+    assert!(header.battery);
+    assert_eq!(header.prg_nvram_size, 0x2000);
+    assert_eq!(header.timing, Timing::Pal);
+}
+
+#[test]
+fn database_lookup() {
+    let ines = read_file("./assets/tests/nestest.nes").unwrap();
+
+    assert_eq!(ines.crc32, 0x158B0388);
+    assert_eq!(ines.database_name.as_deref(), Some("nestest"));
+}
+
+#[test]
+fn database_fixes_bad_header() {
+    // nestest with a DiskDude! tail and a mapper 1, horizontal header
+    let mut file = std::fs::read("./assets/tests/nestest.nes").unwrap();
+    file[6] = 0x10;
+    file[7..16].copy_from_slice(b"DiskDude!");
+
+    let ines = parse_rom(&file).unwrap();
+    assert_eq!(ines.header.mapper, 0);
+    assert_eq!(ines.header.mirroring, Mirroring::Vertical);
+    assert_eq!(ines.database_name.as_deref(), Some("nestest"));
+}
+
+#[test]
+fn nes20db_import() {
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<nes20db date="2024-01-01">
+<!-- Licensed\Some Game (Europe).nes -->
+<game>
+	<prgrom size="131072" crc32="11111111" sha1="AAAA" sum16="1234"/>
+	<rom size="131072" crc32="1234ABCD" sha1="0123456789ABCDEF0123456789ABCDEF01234567"/>
+	<prgnvram size="8192"/>
+	<chrram size="8192"/>
+	<pcb mapper="1" submapper="0" mirroring="H" battery="1"/>
+	<console type="0" region="1"/>
+</game>
+<!-- Unlicensed/Broken.nes -->
+<game>
+	<pcb mapper="0" submapper="0" mirroring="V" battery="0"/>
+</game>
+</nes20db>"#;
+
+    let lines = database::from_nes20db(xml);
+    assert_eq!(
+        lines,
+        [
+            "1234ABCD 0123456789abcdef0123456789abcdef01234567 1 0 H 0 8192 8192 PAL Some Game (Europe)"
+        ]
+    );
+    let sha1 = [
+        0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0xCD, 0xEF, 0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0xCD,
+        0xEF, 0x01, 0x23, 0x45, 0x67,
+    ];
+    let entry = database::find_entry(&lines[0], 0x1234ABCD, &sha1).unwrap();
+    assert_eq!(entry.prg_nvram_size, 0x2000);
+    assert_eq!(entry.timing, Timing::Pal);
+}
+
+fn unif_chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    [id.as_slice(), &(data.len() as u32).to_le_bytes(), data].concat()
+}
+
+#[test]
+fn unif() {
+    let mut file = b"UNIF".to_vec();
+    file.extend(7u32.to_le_bytes());
+    file.extend([0; 24]);
+    file.extend(unif_chunk(b"MAPR", b"NES-NROM-256\0"));
+    file.extend(unif_chunk(b"PRG1", &[0x22; 0x4000]));
+    file.extend(unif_chunk(b"PRG0", &[0x11; 0x4000]));
+    file.extend(unif_chunk(b"MIRR", &[1]));
+    file.extend(unif_chunk(b"BATR", &[1]));
+    let ines = parse_rom(&file).unwrap();
+
+    assert_eq!(ines.board.as_deref(), Some("NES-NROM-256"));
+    assert_eq!(ines.header.format, HeaderFormat::Unif);
+    assert_eq!(ines.header.mapper, 0);
+    assert_eq!(ines.header.mirroring, Mirroring::Vertical);
+    assert!(ines.header.battery);
+    assert_eq!(ines.header.chr_ram_size, 0x2000);
+    assert_eq!(ines.prg_rom[0], 0x11);
+    assert_eq!(ines.prg_rom[0x4000], 0x22);
+
+    let mut unknown_board = file[0..32].to_vec();
+    unknown_board.extend(unif_chunk(b"MAPR", b"UNL-SOMETHING\0"));
+    assert!(matches!(
+        parse_rom(&unknown_board),
+        Err(RomError::UnsupportedBoard(board)) if board == "UNL-SOMETHING"
+    ));
+
+    // Known boards on mappers that aren't emulated say which mapper they need
+    let mut pirate_board = file[0..32].to_vec();
+    pirate_board.extend(unif_chunk(b"MAPR", b"UNL-SA-NROM\0"));
+    pirate_board.extend(unif_chunk(b"PRG0", &[0; 0x8000]));
+    assert!(matches!(
+        parse_rom(&pirate_board),
+        Err(RomError::UnsupportedBoardMapper { board, mapper: 143 }) if board == "UNL-SA-NROM"
+    ));
+}
+
+fn fds_side() -> Vec<u8> {
+    let mut side = vec![0x01];
+    side.extend(b"*NINTENDO-HVC*");
+    side.resize(56, 0);
+    side.extend([0x02, 0x01]);
+    let mut file_header = vec![0x03; 16];
+    file_header[13..15].copy_from_slice(&4u16.to_le_bytes());
+    side.extend(file_header);
+    side.extend([0x04, 0xDE, 0xAD, 0xBE, 0xEF]);
+    side
+}
+
+#[test]
+fn fds_image() {
+    let mut side = fds_side();
+    side.resize(fds::SIDE_SIZE, 0);
+    let mut file = b"FDS\x1A\x02\0\0\0\0\0\0\0\0\0\0\0".to_vec();
+    file.extend(&side);
+    file.extend(&side);
+    let ines = parse_rom(&file).unwrap();
+
+    assert_eq!(ines.header.format, HeaderFormat::Fds);
+    assert_eq!(ines.header.mapper, 20);
+    assert_eq!(ines.disk_sides.len(), 2);
+    assert_eq!(ines.disk_sides[1], side);
+
+    // Quick Disk keeps a CRC after every block
+    let mut qd = vec![];
+    let mut pointer = 0;
+    for length in [56, 2, 16, 5] {
+        qd.extend(&side[pointer..pointer + length]);
+        qd.extend([0xCC, 0xCC]);
+        pointer += length;
+    }
+    qd.resize(0x10000, 0);
+    let ines = parse_rom(&qd).unwrap();
+    assert_eq!(ines.disk_sides, [side]);
+
+    assert!(matches!(
+        parse_rom(&file[..100]),
+        Err(RomError::Truncated {
+            section: "disk side",
+            ..
+        })
+    ));
+}
+
+#[test]
+fn ips_round_trip() {
+    let source = vec![0; 0x500000];
+    let mut target = source.clone();
+    target[0x10..0x20].fill(0x11);
+    target[0x454F46] = 0x22;
+    target[0x454F50] = 0x33;
+    let patch = ips::create(&source, &target);
+
+    assert!(patch.starts_with(b"PATCH") && patch.ends_with(b"EOF"));
+    assert_eq!(ips::apply(&source, &patch).unwrap(), target);
+    assert!(matches!(
+        ips::apply(&source, b"PATCH\0\0"),
+        Err(RomError::BadPatch(_))
+    ));
+}
+
+fn nsf_file(program: &[u8]) -> Vec<u8> {
+    let mut file = vec![0; 0x80];
+    file[0..5].copy_from_slice(b"NESM\x1A");
+    file[0x05] = 1;
+    file[0x06] = 3;
+    file[0x07] = 2;
+    file[0x08..0x0A].copy_from_slice(&0x8000u16.to_le_bytes());
+    file[0x0A..0x0C].copy_from_slice(&0x8000u16.to_le_bytes());
+    file[0x0C..0x0E].copy_from_slice(&0x8003u16.to_le_bytes());
+    file[0x0E..0x14].copy_from_slice(b"Title\0");
+    file[0x6E..0x70].copy_from_slice(&16639u16.to_le_bytes());
+    file[0x7B] = 0b0000_0101;
+    file.extend_from_slice(program);
+    file
+}
+
+#[test]
+fn nsf() {
+    let ines = parse_rom(&nsf_file(&[0x85, 0x00, 0x60, 0xE6, 0x01, 0x60])).unwrap();
+    let info = ines.nsf.unwrap();
+
+    assert_eq!(ines.header.format, HeaderFormat::Nsf);
+    assert_eq!(ines.prg_rom.len(), 0x8000);
+    assert_eq!(ines.prg_rom[3], 0xE6);
+    assert_eq!(info.title, "Title");
+    assert_eq!(info.songs, 3);
+    assert_eq!(info.starting_song, 1);
+    assert_eq!(info.play_address, 0x8003);
+    assert_eq!(info.bankswitch, Some([0, 1, 2, 3, 4, 5, 6, 7]));
+    assert_eq!(info.expansion_chip_names(), ["VRC6", "FDS"]);
+
+    let mut low = nsf_file(&[0x60]);
+    low[0x08..0x0A].copy_from_slice(&0x6000u16.to_le_bytes());
+    assert!(matches!(parse_rom(&low), Err(RomError::BadNsf(_))));
+}
+
+#[test]
+fn nsfe() {
+    let chunk = |id: &[u8; 4], data: &[u8]| {
+        [&(data.len() as u32).to_le_bytes(), id.as_slice(), data].concat()
+    };
+    let mut file = b"NSFE".to_vec();
+    file.extend(chunk(
+        b"INFO",
+        &[0x00, 0x90, 0x00, 0x90, 0x03, 0x90, 0x00, 0x00, 0x02, 0x00],
+    ));
+    file.extend(chunk(b"BANK", &[0, 1]));
+    file.extend(chunk(b"DATA", &[0x60; 0x10]));
+    file.extend(chunk(b"auth", b"Song\0Someone\0\0"));
+    file.extend(chunk(b"tlbl", b"Intro\0Boss\0"));
+    file.extend(chunk(b"time", &[0xE8, 0x03, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF]));
+    file.extend(chunk(b"NEND", &[]));
+    let ines = parse_rom(&file).unwrap();
+    let info = ines.nsf.unwrap();
+
+    assert_eq!(info.load_address, 0x9000);
+    assert_eq!(info.bankswitch, Some([0, 1, 0, 0, 0, 0, 0, 0]));
+    assert_eq!(info.title, "Song");
+    assert_eq!(info.artist, "Someone");
+    assert_eq!(info.track_labels, ["Intro", "Boss"]);
+    assert_eq!(info.track_times, [Some(1000), None]);
+    assert_eq!(info.ntsc_speed, 16639);
+    assert_eq!(ines.prg_rom.len(), 0x1000);
+
+    let mut required = b"NSFE".to_vec();
+    required.extend(chunk(b"INFO", &[0; 10]));
+    required.extend(chunk(b"VRC7", &[0]));
+    assert!(matches!(parse_rom(&required), Err(RomError::BadNsf(_))));
+}
+
+fn encode_number(mut number: usize, patch: &mut Vec<u8>) {
+    loop {
+        let byte = (number & 0x7F) as u8;
+        number >>= 7;
+        if number == 0 {
+            patch.push(0x80 | byte);
+            break;
+        }
+        patch.push(byte);
+        number -= 1;
+    }
+}
+
+fn with_footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+    patch.extend(checksum::crc32(source).to_le_bytes());
+    patch.extend(checksum::crc32(target).to_le_bytes());
+    patch.extend(checksum::crc32(&patch).to_le_bytes());
+    patch
+}
+
+#[test]
+fn bps() {
+    let source = b"hello world, hello";
+    let target = b"hello there, hello hello";
+    let mut patch = b"BPS1".to_vec();
+    encode_number(source.len(), &mut patch);
+    encode_number(target.len(), &mut patch);
+    encode_number(0, &mut patch);
+    // SourceRead "hello ", TargetRead "there", SourceCopy ", hello" from 11, TargetCopy " hello" from 12
+    encode_number((6 - 1) << 2, &mut patch);
+    encode_number(((5 - 1) << 2) | 1, &mut patch);
+    patch.extend(b"there");
+    encode_number(((7 - 1) << 2) | 2, &mut patch);
+    encode_number(11 << 1, &mut patch);
+    encode_number(((6 - 1) << 2) | 3, &mut patch);
+    encode_number(12 << 1, &mut patch);
+    let patch = with_footer(patch, source, target);
+
+    assert_eq!(apply_patch(source, &patch).unwrap(), target);
+    assert!(matches!(
+        apply_patch(b"something else", &patch),
+        Err(RomError::PatchMismatch { format: "BPS", .. })
+    ));
+
+    let mut corrupted = patch.clone();
+    corrupted[10] ^= 0xFF;
+    assert!(matches!(
+        apply_patch(source, &corrupted),
+        Err(RomError::BadPatch(_))
+    ));
+
+    // Well-formed patches with absurd sizes are rejected rather than overflowing or allocating
+    let huge_sizes = |target_size: usize, metadata_size: usize, actions: &[usize]| {
+        let mut patch = b"BPS1".to_vec();
+        encode_number(source.len(), &mut patch);
+        encode_number(target_size, &mut patch);
+        encode_number(metadata_size, &mut patch);
+        for &action in actions {
+            encode_number(action, &mut patch);
+        }
+        with_footer(patch, source, target)
+    };
+    for patch in [
+        huge_sizes(target.len(), usize::MAX - 2, &[0]),
+        huge_sizes(usize::MAX >> 8, 0, &[3, 0]),
+        // SourceRead one byte, then TargetCopy it over and over
+        huge_sizes(target.len(), 0, &[0, ((usize::MAX >> 4) << 2) | 3, 0]),
+    ] {
+        assert!(matches!(
+            apply_patch(source, &patch),
+            Err(RomError::BadPatch(_))
+        ));
+    }
+}
+
+#[test]
+fn ups() {
+    let source = b"hello world";
+    let target = b"hello there!";
+    let mut patch = b"UPS1".to_vec();
+    encode_number(source.len(), &mut patch);
+    encode_number(target.len(), &mut patch);
+    encode_number(6, &mut patch);
+    patch.extend(source[6..].iter().zip(&target[6..11]).map(|(a, b)| a ^ b));
+    patch.extend([b'!', 0]);
+    let patch = with_footer(patch, source, target);
+
+    assert_eq!(apply_patch(source, &patch).unwrap(), target);
+    assert!(matches!(
+        apply_patch(b"hello", &patch),
+        Err(RomError::PatchMismatch { format: "UPS", .. })
+    ));
+
+    // A target far bigger than the source and patch could fill is rejected before allocating
+    let mut huge = b"UPS1".to_vec();
+    encode_number(source.len(), &mut huge);
+    encode_number(usize::MAX >> 8, &mut huge);
+    let huge = with_footer(huge, source, target);
+    assert!(matches!(
+        apply_patch(source, &huge),
+        Err(RomError::BadPatch(_))
+    ));
+}
+
+#[test]
+fn same_named_patch() {
+    let dir = std::env::temp_dir().join("nemulator-same-named-patch");
+    std::fs::create_dir_all(&dir).unwrap();
+    let rom = dir.join("game.nes");
+    let mut file = b"NES\x1A\x01\x00\0\0\0\0\0\0\0\0\0\0".to_vec();
+    file.extend([0; 0x4000]);
+    std::fs::write(&rom, &file).unwrap();
+
+    let mut patch = b"PATCH".to_vec();
+    patch.extend([0x00, 0x00, 0x10, 0x00, 0x01, 0xEA]);
+    patch.extend(b"EOF");
+    std::fs::write(dir.join("game.ips"), &patch).unwrap();
+
+    let ines = read_file(rom.to_str().unwrap()).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(ines.prg_rom[0], 0xEA);
+}
+
+#[cfg(feature = "archives")]
+#[test]
+fn archives() {
+    let plain = read_file("./assets/tests/nestest.nes").unwrap();
+
+    // Skips readme.txt and the roms/ directory, picks the deflated entry
+    let entries = |path: &str| archive_entries(&std::fs::read(path).unwrap()).unwrap();
+    assert_eq!(
+        entries("./assets/tests/nestest.zip"),
+        ["roms/nestest.nes", "roms/nestest_stored.NES"]
+    );
+    let zipped = read_file("./assets/tests/nestest.zip").unwrap();
+    assert_eq!(zipped.crc32, plain.crc32);
+    let stored = read_file_with(
+        "./assets/tests/nestest.zip",
+        None,
+        Some("roms/nestest_stored.NES"),
+    )
+    .unwrap();
+    assert_eq!(stored.crc32, plain.crc32);
+    assert!(matches!(
+        read_file_with("./assets/tests/nestest.zip", None, Some("nestest.nes")),
+        Err(RomError::MissingEntry(_))
+    ));
+
+    let gzipped = read_file("./assets/tests/nestest.nes.gz").unwrap();
+    assert_eq!(gzipped.crc32, plain.crc32);
+    assert!(entries("./assets/tests/nestest.nes").is_empty());
+
+    // Solid 7z archives with their headers packed too, LZMA2 and LZMA
+    for path in [
+        "./assets/tests/nestest.7z",
+        "./assets/tests/nestest_lzma.7z",
+    ] {
+        assert_eq!(
+            entries(path),
+            ["roms/nestest.nes", "roms/nestest_stored.NES"]
+        );
+        assert_eq!(read_file(path).unwrap().crc32, plain.crc32);
+        let second = read_file_with(path, None, Some("roms/nestest_stored.NES")).unwrap();
+        assert_eq!(second.crc32, plain.crc32);
+    }
+
+    let mut corrupted = std::fs::read("./assets/tests/nestest_lzma.7z").unwrap();
+    corrupted[100] ^= 0xFF;
+    assert!(matches!(
+        load_file("corrupted.7z", corrupted, None, None),
+        Err(RomError::BadArchive(_))
+    ));
+}
+
+#[cfg(feature = "archives")]
+fn corpus(directory: &str) -> Vec<std::path::PathBuf> {
+    let mut paths: Vec<_> = std::fs::read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_file())
+        .collect();
+    paths.sort();
+    paths
+}
+
+// Stored zip entries need no decompressor, everything else waits for the feature
+#[cfg(not(feature = "archives"))]
+#[test]
+fn archives_not_built() {
+    let plain = read_file("./assets/tests/nestest.nes").unwrap();
+    let stored = read_file("./assets/tests/archives/infozip_stored.zip").unwrap();
+    assert_eq!(stored.crc32, plain.crc32);
+    for path in [
+        "./assets/tests/archives/infozip_best.zip",
+        "./assets/tests/archives/libarchive_lzma2.7z",
+    ] {
+        assert!(matches!(
+            read_file(path),
+            Err(RomError::UnsupportedArchive(_))
+        ));
+    }
+}
+
+// Archives written by Info-ZIP, GNU gzip, Python's zipfile and gzip, and libarchive
+#[cfg(feature = "archives")]
+#[test]
+fn archive_corpus() {
+    let plain = read_file("./assets/tests/nestest.nes").unwrap();
+    for path in corpus("./assets/tests/archives") {
+        let ines = read_file(path.to_str().unwrap()).unwrap_or_else(|e| panic!("{path:?}: {e}"));
+        assert_eq!(ines.crc32, plain.crc32, "{path:?}");
+    }
+
+    // Deflate and bzip2 inside 7z are valid but not something sevenz-rust decodes
+    for path in corpus("./assets/tests/archives/unsupported") {
+        assert!(
+            matches!(
+                read_file(path.to_str().unwrap()),
+                Err(RomError::UnsupportedArchive(_))
+            ),
+            "{path:?}"
+        );
+    }
+}
+
+// Damaged copies of the corpus have to come back as errors, never panics or runaway allocations
+#[cfg(feature = "archives")]
+#[test]
+fn archive_fuzz() {
+    let mut state = 0x2545F491u32;
+    let mut random = move |limit: usize| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state as usize % limit
+    };
+    for path in corpus("./assets/tests/archives") {
+        let file = std::fs::read(&path).unwrap();
+        for _ in 0..40 {
+            let mut damaged = file.clone();
+            for _ in 0..1 + random(4) {
+                let offset = random(damaged.len());
+                damaged[offset] ^= 1 + random(0xFF) as u8;
+            }
+            if random(4) == 0 {
+                damaged.truncate(random(damaged.len()));
+            }
+            let _ = archive_entries(&damaged);
+            let _ = load_file("fuzz", damaged, None, None);
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use crate::rom_reader::{
+    ConsoleType, HeaderFormat, Mirroring, RomError, Timing, finish_loading, iNES, iNES_header,
+    read_section,
+};
+
+// Board name (without the NES-/HVC- prefix) to its iNES mapper. Only a few of these mappers
+// are emulated, the rest are listed so loading fails naming the mapper the board needs
+const BOARDS: [(&str, u16); 76] = [
+    ("NROM", 0),
+    ("NROM-128", 0),
+    ("NROM-256", 0),
+    ("RROM", 0),
+    ("SAROM", 1),
+    ("SBROM", 1),
+    ("SCROM", 1),
+    ("SEROM", 1),
+    ("SGROM", 1),
+    ("SKROM", 1),
+    ("SLROM", 1),
+    ("SL1ROM", 1),
+    ("SNROM", 1),
+    ("SOROM", 1),
+    ("SUROM", 1),
+    ("SXROM", 1),
+    ("UNROM", 2),
+    ("UOROM", 2),
+    ("CNROM", 3),
+    ("TBROM", 4),
+    ("TEROM", 4),
+    ("TFROM", 4),
+    ("TGROM", 4),
+    ("TKROM", 4),
+    ("TLROM", 4),
+    ("TR1ROM", 4),
+    ("TSROM", 4),
+    ("TVROM", 4),
+    ("EKROM", 5),
+    ("ELROM", 5),
+    ("ETROM", 5),
+    ("EWROM", 5),
+    ("AMROM", 7),
+    ("ANROM", 7),
+    ("AOROM", 7),
+    ("PNROM", 9),
+    ("FJROM", 10),
+    ("FKROM", 10),
+    ("CPROM", 13),
+    ("BNROM", 34),
+    ("GNROM", 66),
+    ("MHROM", 66),
+    ("TKSROM", 118),
+    ("TLSROM", 118),
+    // Pirate and multicart boards only ever shipped as UNIF, numbered by NES 2.0
+    ("UNL-H2288", 123),
+    ("UNL-22211", 132),
+    ("UNL-SA-72008", 133),
+    ("UNL-Sachen-8259D", 137),
+    ("UNL-Sachen-8259B", 138),
+    ("UNL-Sachen-8259C", 139),
+    ("UNL-Sachen-8259A", 141),
+    ("UNL-SA-NROM", 143),
+    ("UNL-SA-72007", 145),
+    ("UNL-SA-016-1M", 146),
+    ("UNL-TC-U01-1.5M", 147),
+    ("UNL-SA-0037", 148),
+    ("UNL-SA-0036", 149),
+    ("UNL-Sachen-74LS374N", 150),
+    ("UNL-SHERO", 262),
+    ("UNL-KOF97", 263),
+    ("UNL-TF1201", 298),
+    ("UNL-AX5705", 530),
+    ("BMC-SuperHIK8in1", 45),
+    ("BMC-D1038", 59),
+    ("BMC-FK23C", 176),
+    ("BMC-70in1", 236),
+    ("BMC-810544-C-A1", 261),
+    ("BMC-T-262", 265),
+    ("BMC-GS-2004", 283),
+    ("BMC-GS-2013", 283),
+    ("BMC-A65AS", 285),
+    ("BMC-BS-5", 286),
+    ("BMC-411120-C", 287),
+    ("BMC-NTD-03", 290),
+    ("BMC-190in1", 300),
+    ("BMC-8157", 301),
+];
+
+fn board_mapper(board: &str) -> Option<u16> {
+    let name = board
+        .strip_prefix("NES-")
+        .or_else(|| board.strip_prefix("HVC-"))
+        .unwrap_or(board);
+
+    BOARDS
+        .iter()
+        .find(|(board_name, _)| *board_name == name)
+        .map(|&(_, mapper)| mapper)
+}
+
+// PRG0..PRGF / CHR0..CHRF, the ROM is the chunks concatenated in that order
+fn numbered_chunk(id: &[u8], prefix: &[u8; 3]) -> Option<usize> {
+    if &id[0..3] != prefix {
+        return None;
+    }
+    (id[3] as char).to_digit(16).map(|digit| digit as usize)
+}
+
+// NES 2.0 default expansion device from the CTRL bitfield
+fn expansion_device(controllers: u8) -> u8 {
+    if controllers & 0b0010_0000 != 0 {
+        0x02 // Four Score
+    } else if controllers & 0b0000_0010 != 0 {
+        0x08 // Zapper
+    } else if controllers & 0b0000_1000 != 0 {
+        0x0F // Arkanoid controller
+    } else if controllers & 0b0001_0000 != 0 {
+        0x0B // Power Pad
+    } else {
+        0x01 // Standard controllers
+    }
+}
+
+pub fn parse_unif(file: &[u8]) -> Result<iNES, RomError> {
+    if file.len() < 32 || file[0..4] != *b"UNIF" {
+        return Err(RomError::BadMagic);
+    }
+
+    let mut board = None;
+    let mut prg_chunks: [Vec<u8>; 16] = Default::default();
+    let mut chr_chunks: [Vec<u8>; 16] = Default::default();
+    let mut mirroring = Mirroring::Horizontal;
+    let mut battery = false;
+    let mut timing = Timing::Ntsc;
+    let mut controllers = 0;
+
+    let mut pointer = 32;
+    while pointer < file.len() {
+        let chunk_header = read_section(file, &mut pointer, 8, "UNIF chunk header")?;
+        let id = &chunk_header[0..4];
+        let length = u32::from_le_bytes(chunk_header[4..8].try_into().unwrap()) as usize;
+        let data = read_section(file, &mut pointer, length, "UNIF chunk")?;
+
+        if let Some(index) = numbered_chunk(id, b"PRG") {
+            prg_chunks[index] = data.to_vec();
+        } else if let Some(index) = numbered_chunk(id, b"CHR") {
+            chr_chunks[index] = data.to_vec();
+        } else {
+            match id {
+                b"MAPR" => {
+                    let name = data.split(|&b| b == 0).next().unwrap_or_default();
+                    board = Some(String::from_utf8_lossy(name).trim().to_string());
+                }
+                b"MIRR" => {
+                    mirroring = match data.first() {
+                        Some(1) => Mirroring::Vertical,
+                        Some(2) => Mirroring::SingleScreenLower,
+                        Some(3) => Mirroring::SingleScreenUpper,
+                        Some(4) => Mirroring::FourScreen,
+                        // 5 is mapper controlled, the mapper sets it at runtime
+                        _ => Mirroring::Horizontal,
+                    }
+                }
+                b"BATR" => battery = data.first().is_some_and(|&b| b != 0),
+                b"TVCI" => {
+                    timing = match data.first() {
+                        Some(1) => Timing::Pal,
+                        Some(2) => Timing::MultiRegion,
+                        _ => Timing::Ntsc,
+                    }
+                }
+                b"CTRL" => controllers = data.first().copied().unwrap_or(0),
+                // NAME, READ, DINF, PCK*, CCK*, ... carry nothing the emulator needs
+                _ => {}
+            }
+        }
+    }
+
+    let board = board.ok_or(RomError::UnsupportedBoard(
+        "<missing MAPR chunk>".to_string(),
+    ))?;
+    let mapper = board_mapper(&board).ok_or_else(|| RomError::UnsupportedBoard(board.clone()))?;
+    let prg_rom = prg_chunks.concat();
+    let chr_rom = chr_chunks.concat();
+
+    let ines_header = iNES_header {
+        format: HeaderFormat::Unif,
+        prg_rom_size: prg_rom.len(),
+        chr_rom_size: chr_rom.len(),
+        mapper,
+        submapper: 0,
+        mirroring,
+        battery,
+        trainer: false,
+        prg_ram_size: if battery { 0 } else { 0x2000 },
+        prg_nvram_size: if battery { 0x2000 } else { 0 },
+        chr_ram_size: 0,
+        chr_nvram_size: 0,
+        timing,
+        console_type: ConsoleType::Nes,
+        misc_roms: 0,
+        expansion_device: expansion_device(controllers),
+    };
+
+    finish_loading(ines_header, vec![], prg_rom, chr_rom, Some(board.clone())).map_err(
+        |e| match e {
+            RomError::UnsupportedMapper(mapper) => {
+                RomError::UnsupportedBoardMapper { board, mapper }
+            }
+            e => e,
+        },
+    )
+}
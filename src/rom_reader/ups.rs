@@ -0,0 +1,59 @@
+use crate::checksum;
+use crate::rom_reader::RomError;
+use crate::rom_reader::bps::{decode_number, read_footer};
+
+fn bad_patch() -> RomError {
+    RomError::BadPatch("UPS".to_string())
+}
+
+pub fn apply(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, RomError> {
+    if !patch.starts_with(b"UPS1") {
+        return Err(bad_patch());
+    }
+    let [source_crc, target_crc, patch_crc] = read_footer(patch).ok_or_else(bad_patch)?;
+    if checksum::crc32(&patch[..patch.len() - 4]) != patch_crc {
+        return Err(bad_patch());
+    }
+    let found = checksum::crc32(source);
+    if found != source_crc {
+        return Err(RomError::PatchMismatch {
+            format: "UPS",
+            expected: source_crc,
+            found,
+        });
+    }
+
+    let hunks_end = patch.len() - 12;
+    let mut pointer = 4;
+    let _source_size = decode_number(patch, &mut pointer).ok_or_else(bad_patch)?;
+    let target_size = decode_number(patch, &mut pointer).ok_or_else(bad_patch)?;
+    // The size comes from the patch, anything bigger than the data could fill is bogus
+    if target_size > source.len() + patch.len() {
+        return Err(bad_patch());
+    }
+
+    let mut target = source.to_vec();
+    target.resize(target_size, 0);
+    let mut output = 0;
+    while pointer < hunks_end {
+        output += decode_number(patch, &mut pointer).ok_or_else(bad_patch)?;
+        // XOR bytes up to and including a 0 terminator
+        loop {
+            let byte = *patch.get(pointer).ok_or_else(bad_patch)?;
+            pointer += 1;
+            if let Some(target_byte) = target.get_mut(output) {
+                *target_byte ^= byte;
+            }
+            output += 1;
+            if byte == 0 {
+                break;
+            }
+        }
+    }
+
+    if checksum::crc32(&target) != target_crc {
+        return Err(bad_patch());
+    }
+
+    Ok(target)
+}